@@ -1,263 +1,3633 @@
 use anchor_lang::prelude::*;
+use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, Transfer};
 
 declare_id!("11111111111111111111111111111111");
 
+/// Prices are quoted in basis points of one unit of collateral.
+pub const PRICE_SCALE: u64 = 10_000;
+
+/// Order sizes must be a multiple of this many base units so that
+/// `price * size / PRICE_SCALE` is always exact.
+pub const LOT_SIZE: u64 = PRICE_SCALE;
+
+/// Number of resting orders each side of an order book can hold.
+pub const ORDER_BOOK_DEPTH: usize = 1024;
+
+/// Number of unsettled fills a market's event queue can hold.
+pub const EVENT_QUEUE_LEN: usize = 256;
+
+/// Resting orders an incoming order may cross in one instruction, keeping
+/// matching within the compute budget however thin the top of the book is.
+pub const MAX_MATCHES_PER_ORDER: usize = 32;
+
+/// Expired resting orders an incoming order may skip over in one
+/// instruction. Skipping is only a comparison, so this is bounded separately
+/// and far higher than the match limit, keeping orders that have expired but
+/// not yet been pruned from blocking the live liquidity behind them.
+pub const MAX_EXPIRED_SKIPS_PER_ORDER: usize = 256;
+
+/// Upper bound for any configured fee, in basis points.
+pub const MAX_FEE_BPS: u16 = 1_000;
+
+/// Most outcomes a categorical market can list. Splitting and merging pass
+/// a mint and a token account per outcome, and eight outcomes keep those
+/// within a legacy transaction without address lookup tables.
+pub const MAX_OUTCOMES: u8 = 8;
+
 #[program]
 pub mod betting_exchange {
     use super::*;
 
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        min_order_size: u64,
+        arbiter: Pubkey,
+        resolution_bond: u64,
+        dispute_window: i64,
+    ) -> Result<()> {
+        require!(
+            maker_fee_bps <= MAX_FEE_BPS && taker_fee_bps <= MAX_FEE_BPS,
+            ErrorCode::InvalidFee
+        );
+        require!(
+            min_order_size > 0 && min_order_size % LOT_SIZE == 0,
+            ErrorCode::InvalidOrderSize
+        );
+        require!(dispute_window > 0, ErrorCode::InvalidDisputeWindow);
+
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.collateral_mint = ctx.accounts.collateral_mint.key();
+        config.maker_fee_bps = maker_fee_bps;
+        config.taker_fee_bps = taker_fee_bps;
+        config.min_order_size = min_order_size;
+        config.arbiter = arbiter;
+        config.resolution_bond = resolution_bond;
+        config.dispute_window = dispute_window;
+        config.paused = false;
+        config.bump = ctx.bumps.config;
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_config(
+        ctx: Context<UpdateConfig>,
+        admin: Option<Pubkey>,
+        collateral_mint: Option<Pubkey>,
+        maker_fee_bps: Option<u16>,
+        taker_fee_bps: Option<u16>,
+        min_order_size: Option<u64>,
+        arbiter: Option<Pubkey>,
+        resolution_bond: Option<u64>,
+        dispute_window: Option<i64>,
+        paused: Option<bool>,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+
+        if let Some(admin) = admin {
+            config.admin = admin;
+        }
+        if let Some(collateral_mint) = collateral_mint {
+            config.collateral_mint = collateral_mint;
+        }
+        if let Some(maker_fee_bps) = maker_fee_bps {
+            require!(maker_fee_bps <= MAX_FEE_BPS, ErrorCode::InvalidFee);
+            config.maker_fee_bps = maker_fee_bps;
+        }
+        if let Some(taker_fee_bps) = taker_fee_bps {
+            require!(taker_fee_bps <= MAX_FEE_BPS, ErrorCode::InvalidFee);
+            config.taker_fee_bps = taker_fee_bps;
+        }
+        if let Some(min_order_size) = min_order_size {
+            require!(
+                min_order_size > 0 && min_order_size % LOT_SIZE == 0,
+                ErrorCode::InvalidOrderSize
+            );
+            config.min_order_size = min_order_size;
+        }
+        if let Some(arbiter) = arbiter {
+            config.arbiter = arbiter;
+        }
+        if let Some(resolution_bond) = resolution_bond {
+            config.resolution_bond = resolution_bond;
+        }
+        if let Some(dispute_window) = dispute_window {
+            require!(dispute_window > 0, ErrorCode::InvalidDisputeWindow);
+            config.dispute_window = dispute_window;
+        }
+        if let Some(paused) = paused {
+            config.paused = paused;
+        }
+
+        emit!(ConfigUpdated {
+            admin: config.admin,
+            collateral_mint: config.collateral_mint,
+            maker_fee_bps: config.maker_fee_bps,
+            taker_fee_bps: config.taker_fee_bps,
+            min_order_size: config.min_order_size,
+            arbiter: config.arbiter,
+            resolution_bond: config.resolution_bond,
+            dispute_window: config.dispute_window,
+            paused: config.paused,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_treasury(_ctx: Context<InitializeTreasury>) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            ctx.accounts.treasury.amount >= amount,
+            ErrorCode::InsufficientBalance
+        );
+
+        let config = &ctx.accounts.config;
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.treasury.to_account_info(),
+                    to: ctx.accounts.destination.to_account_info(),
+                    authority: config.to_account_info(),
+                },
+                &[&[b"config".as_ref(), &[config.bump]]],
+            ),
+            amount,
+        )?;
+
+        emit!(FeesWithdrawn {
+            treasury: ctx.accounts.treasury.key(),
+            destination: ctx.accounts.destination.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Markets bound to an `oracle` resolve only through
+    /// `resolve_from_oracle`; others go through `propose_resolution`.
+    /// `kind` is either binary or scalar, whose LONG and SHORT tokens are
+    /// the YES and NO mints.
     pub fn initialize_market(
         ctx: Context<InitializeMarket>,
         title: String,
         description: String,
         expiry_timestamp: i64,
+        oracle: Option<OracleConfig>,
+        kind: MarketKind,
     ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ErrorCode::ProtocolPaused);
+        require!(
+            !title.is_empty() && title.len() <= Market::MAX_TITLE_LEN,
+            ErrorCode::InvalidTitle
+        );
+        require!(
+            description.len() <= Market::MAX_DESCRIPTION_LEN,
+            ErrorCode::DescriptionTooLong
+        );
+        require!(
+            expiry_timestamp > Clock::get()?.unix_timestamp,
+            ErrorCode::InvalidExpiry
+        );
+        match kind {
+            MarketKind::Binary => {}
+            MarketKind::Scalar { lower, upper } => {
+                require!(lower < upper, ErrorCode::InvalidScalarBounds)
+            }
+            MarketKind::Categorical { .. } => return err!(ErrorCode::NotBinaryMarket),
+        }
+        if let Some(oracle) = &oracle {
+            oracle.validate()?;
+        }
+
         let market = &mut ctx.accounts.market;
         market.creator = ctx.accounts.creator.key();
         market.title = title;
         market.description = description;
         market.expiry_timestamp = expiry_timestamp;
+        market.kind = kind;
         market.is_active = true;
         market.is_resolved = false;
+        market.oracle = oracle;
+        market.yes_token_mint = Some(ctx.accounts.yes_mint.key());
+        market.no_token_mint = Some(ctx.accounts.no_mint.key());
         market.yes_token_supply = 0;
         market.no_token_supply = 0;
+        market.collateral_mint = ctx.accounts.collateral_mint.key();
+        market.bump = ctx.bumps.market;
+        market.vault_bump = ctx.bumps.vault;
+
+        Ok(())
+    }
+
+    /// Creates a categorical market with `outcomes` mutually exclusive
+    /// outcomes, each with its own mint created by `initialize_outcome_mint`.
+    /// Categorical markets trade through complete sets rather than an order
+    /// book, and resolve to a single winning outcome index.
+    pub fn initialize_categorical_market(
+        ctx: Context<InitializeCategoricalMarket>,
+        title: String,
+        description: String,
+        expiry_timestamp: i64,
+        outcomes: u8,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ErrorCode::ProtocolPaused);
+        require!(
+            !title.is_empty() && title.len() <= Market::MAX_TITLE_LEN,
+            ErrorCode::InvalidTitle
+        );
+        require!(
+            description.len() <= Market::MAX_DESCRIPTION_LEN,
+            ErrorCode::DescriptionTooLong
+        );
+        require!(
+            expiry_timestamp > Clock::get()?.unix_timestamp,
+            ErrorCode::InvalidExpiry
+        );
+        require!(
+            (2..=MAX_OUTCOMES).contains(&outcomes),
+            ErrorCode::InvalidOutcomeCount
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.creator = ctx.accounts.creator.key();
+        market.title = title;
+        market.description = description;
+        market.expiry_timestamp = expiry_timestamp;
+        market.kind = MarketKind::Categorical { outcomes };
+        market.is_active = true;
+        market.is_resolved = false;
+        market.collateral_mint = ctx.accounts.collateral_mint.key();
         market.bump = ctx.bumps.market;
-        
+        market.vault_bump = ctx.bumps.vault;
+
+        Ok(())
+    }
+
+    pub fn initialize_outcome_mint(ctx: Context<InitializeOutcomeMint>, index: u8) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        match market.kind {
+            MarketKind::Categorical { outcomes } if index < outcomes => {}
+            _ => return err!(ErrorCode::InvalidOutcomeIndex),
+        }
+        market.outcome_mint_bumps[index as usize] = ctx.bumps.outcome_mint;
+
+        Ok(())
+    }
+
+    pub fn initialize_order_book(ctx: Context<InitializeOrderBook>) -> Result<()> {
+        let mut order_book = ctx.accounts.order_book.load_init()?;
+        order_book.market = ctx.accounts.market.key();
+
+        let mut event_queue = ctx.accounts.event_queue.load_init()?;
+        event_queue.market = ctx.accounts.market.key();
+
+        ctx.accounts.market.order_book = ctx.accounts.order_book.key();
+        ctx.accounts.market.event_queue = ctx.accounts.event_queue.key();
+
+        Ok(())
+    }
+
+    pub fn initialize_trader_account(ctx: Context<InitializeTraderAccount>) -> Result<()> {
+        let trader_account = &mut ctx.accounts.trader_account;
+        trader_account.market = ctx.accounts.market.key();
+        trader_account.owner = ctx.accounts.user.key();
+        trader_account.open_orders = Vec::new();
+        trader_account.bump = ctx.bumps.trader_account;
+
+        Ok(())
+    }
+
+    /// Moves collateral from the user's token account into the vault and
+    /// credits it to their free balance, which orders lock against.
+    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ErrorCode::ProtocolPaused);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_collateral.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+        ctx.accounts.trader_account.deposit(amount)?;
+
+        emit!(Deposited {
+            market: ctx.accounts.market.key(),
+            user: ctx.accounts.user.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Pays out collateral from the user's free balance. Collateral locked
+    /// in open orders has to be released by cancelling them first.
+    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        ctx.accounts.trader_account.withdraw(amount)?;
+
+        let market = &ctx.accounts.market;
+        let seeds = market.signer_seeds();
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault.to_account_info(),
+                    to: ctx.accounts.user_collateral.to_account_info(),
+                    authority: market.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+        )?;
+
+        emit!(Withdrawn {
+            market: market.key(),
+            user: ctx.accounts.user.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Burns outcome tokens from the user's token account into their
+    /// position, which sell orders lock against.
+    pub fn deposit_tokens(ctx: Context<TransferTokens>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ErrorCode::ProtocolPaused);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let outcome = ctx
+            .accounts
+            .market
+            .outcome_for_mint(&ctx.accounts.outcome_mint.key())?;
+
+        token::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.outcome_mint.to_account_info(),
+                    from: ctx.accounts.user_outcome_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+        ctx.accounts
+            .trader_account
+            .deposit_tokens(outcome, amount)?;
+
+        emit!(TokensDeposited {
+            market: ctx.accounts.market.key(),
+            user: ctx.accounts.user.key(),
+            outcome,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Mints outcome tokens out of the user's position into their token
+    /// account, e.g. to redeem or merge them.
+    pub fn withdraw_tokens(ctx: Context<TransferTokens>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let market = &ctx.accounts.market;
+        let outcome = market.outcome_for_mint(&ctx.accounts.outcome_mint.key())?;
+        ctx.accounts
+            .trader_account
+            .withdraw_tokens(outcome, amount)?;
+
+        let seeds = market.signer_seeds();
+        token::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.outcome_mint.to_account_info(),
+                    to: ctx.accounts.user_outcome_account.to_account_info(),
+                    authority: market.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+        )?;
+
+        emit!(TokensWithdrawn {
+            market: market.key(),
+            user: ctx.accounts.user.key(),
+            outcome,
+            amount,
+        });
+
         Ok(())
     }
 
-    pub fn place_order(
-        ctx: Context<PlaceOrder>,
+    /// Prices are quoted in YES terms for both outcomes, as the book keeps
+    /// them: an order for NO at `price` values each NO token at
+    /// `10000 - price`, so a NO buyer paying 0.40 passes 6000.
+    ///
+    /// Resting orders of the same user that get cancelled by self-trade
+    /// prevention while matching must be passed in the remaining accounts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PlaceOrder<'info>>,
+        client_order_id: u64,
         side: Side,
+        direction: Direction,
         order_type: OrderType,
-        price: u64, // Price in basis points (0-10000, where 10000 = 1.0)
+        self_trade_prevention: SelfTradePrevention,
+        price: u64, // Price in basis points (0-10000, where 10000 = 1.0), in YES terms
         size: u64,
+        expires_at: Option<i64>,
     ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ErrorCode::ProtocolPaused);
+
+        let market = &ctx.accounts.market;
+        require!(
+            market.is_active && !market.is_resolved,
+            ErrorCode::MarketNotActive
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < market.expiry_timestamp, ErrorCode::MarketExpired);
+        require!(
+            expires_at.map_or(true, |expires_at| expires_at > now),
+            ErrorCode::InvalidExpiry
+        );
+        require!(price > 0 && price < PRICE_SCALE, ErrorCode::InvalidPrice);
+        let config = &ctx.accounts.config;
+        require!(
+            size >= config.min_order_size && size % LOT_SIZE == 0,
+            ErrorCode::InvalidOrderSize
+        );
+
+        let fee_bps = config.maker_fee_bps.max(config.taker_fee_bps);
+
         let order = &mut ctx.accounts.order;
         order.market = ctx.accounts.market.key();
         order.user = ctx.accounts.user.key();
+        order.client_order_id = client_order_id;
         order.side = side;
+        order.direction = direction;
         order.order_type = order_type;
+        order.self_trade_prevention = self_trade_prevention;
         order.price = price;
         order.size = size;
         order.filled = 0;
         order.status = OrderStatus::Pending;
+        order.fee_bps = fee_bps;
+        order.expires_at = expires_at;
         order.bump = ctx.bumps.order;
+        let book_side = order.book_side();
+
+        let mut order_book = ctx.accounts.order_book.load_mut()?;
+        if order_type == OrderType::PostOnly {
+            require!(
+                !order_book.would_cross(book_side, price, now),
+                ErrorCode::PostOnlyWouldCross
+            );
+        }
+
+        // Cross against resting orders at their prices, queueing the fills
+        // for settlement, and rest whatever is left in the book
+        let order_key = order.key();
+        let trader_account = &mut ctx.accounts.trader_account;
+        let user = ctx.accounts.user.to_account_info();
+        let mut event_queue = ctx.accounts.event_queue.load_mut()?;
+        let result = order_book.match_order(
+            order,
+            size,
+            now,
+            |maker, fill_size| {
+                event_queue.push(FillEvent::matched(book_side, order_key, maker, fill_size))
+            },
+            |maker, size| {
+                cancel_self_trade_maker(ctx.remaining_accounts, trader_account, &user, maker, size)
+            },
+        )?;
+        let MatchResult {
+            unmatched,
+            cancelled,
+            ..
+        } = result;
+
+        match order_type {
+            OrderType::FillOrKill => {
+                require!(
+                    unmatched == 0 && cancelled == 0,
+                    ErrorCode::FillOrKillNotFilled
+                )
+            }
+            OrderType::Market | OrderType::ImmediateOrCancel => {
+                let filled = size - unmatched - cancelled;
+                require!(filled > 0, ErrorCode::ImmediateOrCancelNotFilled);
+                order.size = filled;
+            }
+            OrderType::Limit | OrderType::PostOnly => {
+                order.size = size - cancelled;
+                require!(order.size > 0, result.cancelled_error());
+                if unmatched > 0 {
+                    order_book.insert(
+                        book_side,
+                        BookEntry {
+                            order: order_key,
+                            owner: order.user,
+                            price,
+                            size: unmatched,
+                            expires_at: expires_at.unwrap_or(0),
+                        },
+                    )?;
+                }
+            }
+        }
+        drop(order_book);
+        drop(event_queue);
+
+        // Lock the worst-case cost of a buy out of the trader's free
+        // balance, plus enough to pay the higher of the maker and taker
+        // fees; a sell locks the outcome tokens it offers
+        let collateral = order.reserved_for(order.size)?;
+        order.locked = collateral;
+        trader_account.open_order(order.key(), collateral)?;
+        if direction == Direction::Sell {
+            trader_account.lock_tokens(side, order.size)?;
+        }
 
-        // Emit order event for off-chain matching engine
         emit!(OrderPlaced {
             order_id: order.key(),
             market: order.market,
             user: order.user,
+            client_order_id: order.client_order_id,
             side: order.side,
+            direction: order.direction,
             order_type: order.order_type,
             price: order.price,
             size: order.size,
+            expires_at: order.expires_at,
         });
 
         Ok(())
     }
 
-    pub fn settle_fill(
-        ctx: Context<SettleFill>,
-        fill_size: u64,
-        fill_price: u64,
-    ) -> Result<()> {
-        let buy_order = &mut ctx.accounts.buy_order;
-        let sell_order = &mut ctx.accounts.sell_order;
-        
-        // Update filled amounts
-        buy_order.filled = buy_order.filled.checked_add(fill_size).unwrap();
-        sell_order.filled = sell_order.filled.checked_add(fill_size).unwrap();
-        
-        // Update order statuses
-        if buy_order.filled >= buy_order.size {
-            buy_order.status = OrderStatus::Filled;
-        } else {
-            buy_order.status = OrderStatus::Partial;
+    pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+        let order = &mut ctx.accounts.order;
+        require!(
+            order.status == OrderStatus::Pending || order.status == OrderStatus::Partial,
+            ErrorCode::OrderNotOpen
+        );
+
+        // Only the size still resting in the book can be cancelled; fills
+        // already matched stay locked until the crank settles them
+        let remaining = ctx
+            .accounts
+            .order_book
+            .load_mut()?
+            .remove(order.book_side(), &order.key())
+            .map_or(0, |entry| entry.size);
+        require!(remaining > 0, ErrorCode::OrderNotOpen);
+        let refund = order.cancel(remaining)?;
+
+        let trader_account = &mut ctx.accounts.trader_account;
+        trader_account.record_cancel(order, remaining, refund)?;
+
+        let market = &ctx.accounts.market;
+        emit!(OrderCancelled {
+            order_id: ctx.accounts.order.key(),
+            market: market.key(),
+            user: ctx.accounts.user.key(),
+            client_order_id: ctx.accounts.order.client_order_id,
+            remaining,
+            refund,
+        });
+
+        // Orders with queued fills stay open for the crank to settle
+        if ctx.accounts.order.status == OrderStatus::Cancelled {
+            ctx.accounts
+                .order
+                .close(ctx.accounts.user.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes an order that can no longer trade or be settled back to its
+    /// user: one that filled, or was cancelled once its queued fills were
+    /// settled.
+    pub fn close_order(ctx: Context<CloseOrder>) -> Result<()> {
+        let order = &ctx.accounts.order;
+        require!(
+            (order.status == OrderStatus::Filled || order.status == OrderStatus::Cancelled)
+                && order.locked == 0,
+            ErrorCode::OrderStillOpen
+        );
+        Ok(())
+    }
+
+    /// Cancels every order passed in the remaining accounts, which must all
+    /// belong to the signing user in this market, returning their collateral
+    /// to the trader's free balance. Orders with nothing left resting are
+    /// skipped.
+    pub fn cancel_all<'info>(ctx: Context<'_, '_, 'info, 'info, CancelAll<'info>>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let user = ctx.accounts.user.key();
+        let mut order_book = ctx.accounts.order_book.load_mut()?;
+        let trader_account = &mut ctx.accounts.trader_account;
+
+        for order_info in ctx.remaining_accounts {
+            let mut order = Account::<Order>::try_from(order_info)?;
+            require_keys_eq!(order.market, market.key(), ErrorCode::OrderMarketMismatch);
+            require_keys_eq!(order.user, user, ErrorCode::Unauthorized);
+            if order.status != OrderStatus::Pending && order.status != OrderStatus::Partial {
+                continue;
+            }
+
+            let remaining = order_book
+                .remove(order.book_side(), &order.key())
+                .map_or(0, |entry| entry.size);
+            if remaining == 0 {
+                continue;
+            }
+            let refund = order.cancel(remaining)?;
+            trader_account.record_cancel(&order, remaining, refund)?;
+
+            emit!(OrderCancelled {
+                order_id: order.key(),
+                market: market.key(),
+                user,
+                client_order_id: order.client_order_id,
+                remaining,
+                refund,
+            });
+
+            if order.status == OrderStatus::Cancelled {
+                order.close(ctx.accounts.user.to_account_info())?;
+            } else {
+                order.exit(&crate::ID)?;
+            }
         }
-        
-        if sell_order.filled >= sell_order.size {
-            sell_order.status = OrderStatus::Filled;
+
+        Ok(())
+    }
+
+    /// Moves a resting order to `new_price` with `new_size` left to fill,
+    /// topping up or refunding its locked collateral. The order keeps its
+    /// place in the queue only when it shrinks at the same price; otherwise
+    /// it is matched again at the new price and requeued, with self-trade
+    /// prevention applied as in `place_order`. `new_price` is in YES terms,
+    /// as in `place_order`.
+    pub fn replace_order<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ReplaceOrder<'info>>,
+        new_price: u64,
+        new_size: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ErrorCode::ProtocolPaused);
+
+        let market = &ctx.accounts.market;
+        require!(
+            market.is_active && !market.is_resolved,
+            ErrorCode::MarketNotActive
+        );
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < market.expiry_timestamp, ErrorCode::MarketExpired);
+        require!(
+            new_price > 0 && new_price < PRICE_SCALE,
+            ErrorCode::InvalidPrice
+        );
+        require!(
+            new_size >= ctx.accounts.config.min_order_size && new_size % LOT_SIZE == 0,
+            ErrorCode::InvalidOrderSize
+        );
+
+        let order = &mut ctx.accounts.order;
+        require!(
+            order.status == OrderStatus::Pending || order.status == OrderStatus::Partial,
+            ErrorCode::OrderNotOpen
+        );
+        require!(!order.is_expired(now), ErrorCode::OrderExpired);
+
+        let order_key = order.key();
+        let side = order.book_side();
+        let mut order_book = ctx.accounts.order_book.load_mut()?;
+        let resting = order_book
+            .get(side, &order_key)
+            .map_or(0, |entry| entry.size);
+        require!(resting > 0, ErrorCode::OrderNotOpen);
+        // Collateral for matched but unsettled fills is locked at the old
+        // price, so those must be settled before the price can move
+        require!(
+            order.filled + resting == order.size,
+            ErrorCode::FillsPending
+        );
+
+        let old_price = order.price;
+        let priority_kept = new_price == old_price && new_size <= resting;
+        order.price = new_price;
+        let mut new_size = new_size;
+        if priority_kept {
+            order_book.reduce(side, &order_key, resting - new_size)?;
         } else {
-            sell_order.status = OrderStatus::Partial;
+            order_book.remove(side, &order_key);
+            if order.order_type == OrderType::PostOnly {
+                require!(
+                    !order_book.would_cross(side, new_price, now),
+                    ErrorCode::PostOnlyWouldCross
+                );
+            }
+
+            let trader_account = &mut ctx.accounts.trader_account;
+            let user = ctx.accounts.user.to_account_info();
+            let mut event_queue = ctx.accounts.event_queue.load_mut()?;
+            let result = order_book.match_order(
+                order,
+                new_size,
+                now,
+                |maker, fill_size| {
+                    event_queue.push(FillEvent::matched(side, order_key, maker, fill_size))
+                },
+                |maker, size| {
+                    cancel_self_trade_maker(
+                        ctx.remaining_accounts,
+                        trader_account,
+                        &user,
+                        maker,
+                        size,
+                    )
+                },
+            )?;
+            new_size -= result.cancelled;
+            require!(new_size > 0, result.cancelled_error());
+            if result.unmatched > 0 {
+                order_book.insert(
+                    side,
+                    BookEntry {
+                        order: order_key,
+                        owner: order.user,
+                        price: new_price,
+                        size: result.unmatched,
+                        expires_at: order.expires_at.unwrap_or(0),
+                    },
+                )?;
+            }
         }
+        drop(order_book);
 
-        // Mint position tokens to users
-        // This would involve CPI calls to SPL Token program
-        // Simplified for skeleton
+        order.size = order
+            .filled
+            .checked_add(new_size)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Lock exactly what the new size needs at the new price
+        let locked = order.reserved_for(new_size)?;
+        if locked > order.locked {
+            ctx.accounts.trader_account.lock(locked - order.locked)?;
+        } else if locked < order.locked {
+            ctx.accounts.trader_account.unlock(order.locked - locked)?;
+        }
+        order.locked = locked;
+        if order.direction == Direction::Sell {
+            if new_size > resting {
+                ctx.accounts
+                    .trader_account
+                    .lock_tokens(order.side, new_size - resting)?;
+            } else if new_size < resting {
+                ctx.accounts
+                    .trader_account
+                    .unlock_tokens(order.side, resting - new_size)?;
+            }
+        }
 
-        emit!(FillSettled {
-            buy_order: buy_order.key(),
-            sell_order: sell_order.key(),
-            fill_size,
-            fill_price,
+        emit!(OrderAmended {
+            order_id: order_key,
+            market: market.key(),
+            user: order.user,
+            client_order_id: order.client_order_id,
+            old_price,
+            new_price,
+            old_size: resting,
+            new_size,
+            priority_kept,
         });
 
         Ok(())
     }
 
-    pub fn resolve_market(
-        ctx: Context<ResolveMarket>,
-        outcome: bool, // true for YES, false for NO
+    /// Permissionless cleanup of expired orders: cancels their resting size
+    /// and returns its collateral to the owner's free balance. Each order
+    /// needs three remaining accounts, in order: the order, its owner, and
+    /// the owner's trader account.
+    pub fn prune_expired_orders<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PruneExpiredOrders<'info>>,
     ) -> Result<()> {
-        let market = &mut ctx.accounts.market;
-        
-        // Only creator can resolve market
+        let now = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.market;
+        let mut order_book = ctx.accounts.order_book.load_mut()?;
+
+        for group in ctx.remaining_accounts.chunks_exact(3) {
+            let [order_info, user, trader_info] = group else {
+                unreachable!();
+            };
+            let mut order = Account::<Order>::try_from(order_info)?;
+            require_keys_eq!(order.market, market.key(), ErrorCode::OrderMarketMismatch);
+            require_keys_eq!(user.key(), order.user, ErrorCode::Unauthorized);
+            require!(order.is_expired(now), ErrorCode::OrderNotExpired);
+            let mut trader_account = load_trader_account(trader_info, &market.key(), &order.user)?;
+
+            let remaining = order_book
+                .remove(order.book_side(), &order.key())
+                .map_or(0, |entry| entry.size);
+            require!(remaining > 0, ErrorCode::OrderNotOpen);
+            let refund = order.cancel(remaining)?;
+            trader_account.record_cancel(&order, remaining, refund)?;
+            trader_account.exit(&crate::ID)?;
+
+            emit!(OrderCancelled {
+                order_id: order.key(),
+                market: market.key(),
+                user: order.user,
+                client_order_id: order.client_order_id,
+                remaining,
+                refund,
+            });
+
+            if order.status == OrderStatus::Cancelled {
+                order.close(user.clone())?;
+            } else {
+                order.exit(&crate::ID)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Permissionless crank that settles fills matched by `place_order`.
+    /// Each queued fill needs four remaining accounts, in order: the bid
+    /// order, the ask order, and the bid's and ask's trader accounts.
+    pub fn consume_events<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ConsumeEvents<'info>>,
+        limit: u16,
+    ) -> Result<()> {
+        let market_key = ctx.accounts.market.key();
+        let mut groups = ctx.remaining_accounts.chunks_exact(4);
+        let mut consumed = 0;
+
+        while consumed < limit {
+            let Some(fill) = ctx.accounts.event_queue.load()?.peek() else {
+                break;
+            };
+            let Some([bid_info, ask_info, bid_trader_info, ask_trader_info]) = groups.next() else {
+                break;
+            };
+
+            require_keys_eq!(
+                bid_info.key(),
+                fill.bid_order,
+                ErrorCode::EventAccountMismatch
+            );
+            require_keys_eq!(
+                ask_info.key(),
+                fill.ask_order,
+                ErrorCode::EventAccountMismatch
+            );
+            let mut bid_order = Account::<Order>::try_from(bid_info)?;
+            let mut ask_order = Account::<Order>::try_from(ask_info)?;
+            let mut bid_trader =
+                load_trader_account(bid_trader_info, &market_key, &bid_order.user)?;
+            let mut ask_trader =
+                load_trader_account(ask_trader_info, &market_key, &ask_order.user)?;
+
+            let accounts = FillAccounts {
+                vault: ctx.accounts.vault.to_account_info(),
+                treasury: ctx.accounts.treasury.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+            };
+            let amounts = execute_fill(
+                &mut ctx.accounts.market,
+                &ctx.accounts.config,
+                &mut bid_order,
+                &mut ask_order,
+                &accounts,
+                &fill,
+            )?;
+            record_fill(
+                &mut bid_trader,
+                &mut ask_trader,
+                &bid_order,
+                &ask_order,
+                fill.size,
+                &amounts,
+            )?;
+            bid_order.exit(&crate::ID)?;
+            ask_order.exit(&crate::ID)?;
+            bid_trader.exit(&crate::ID)?;
+            ask_trader.exit(&crate::ID)?;
+
+            ctx.accounts.event_queue.load_mut()?.pop();
+            consumed += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Proposes the outcome of an expired market, posting the resolution
+    /// bond. The proposal becomes final unless disputed within the dispute
+    /// window.
+    pub fn propose_resolution(ctx: Context<ProposeResolution>, outcome: Resolution) -> Result<()> {
+        let market = &ctx.accounts.market;
+        outcome.validate(market.kind)?;
+        require!(market.oracle.is_none(), ErrorCode::OracleBound);
+        require!(!market.is_resolved, ErrorCode::MarketAlreadyResolved);
         require!(
-            market.creator == ctx.accounts.creator.key(),
-            ErrorCode::Unauthorized
+            market.proposal.is_none(),
+            ErrorCode::ResolutionAlreadyProposed
         );
-
-        // Check if market has expired
         let current_timestamp = Clock::get()?.unix_timestamp;
         require!(
             current_timestamp >= market.expiry_timestamp,
             ErrorCode::MarketNotExpired
         );
 
-        market.is_resolved = true;
-        market.resolution = Some(outcome);
+        let config = &ctx.accounts.config;
+        let bond = config.resolution_bond;
+        let dispute_deadline = current_timestamp
+            .checked_add(config.dispute_window)
+            .ok_or(ErrorCode::MathOverflow)?;
+        post_bond(&ctx, bond)?;
+
+        let market = &mut ctx.accounts.market;
+        market.proposal = Some(Proposal {
+            proposer: ctx.accounts.bonder.key(),
+            outcome,
+            proposed_at: current_timestamp,
+            dispute_deadline,
+            bond,
+            disputer: None,
+        });
 
-        emit!(MarketResolved {
+        emit!(ResolutionProposed {
             market: market.key(),
+            proposer: ctx.accounts.bonder.key(),
             outcome,
+            bond,
+            dispute_deadline,
         });
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct InitializeMarket<'info> {
-    #[account(
-        init,
-        payer = creator,
-        space = Market::LEN,
-        seeds = [b"market", creator.key().as_ref(), title.as_bytes()],
-        bump
-    )]
-    pub market: Account<'info, Market>,
-    #[account(mut)]
-    pub creator: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
+    /// Disputes a pending proposal within the dispute window by matching its
+    /// bond, escalating the outcome to the arbiter.
+    pub fn dispute_resolution(ctx: Context<ProposeResolution>) -> Result<()> {
+        let proposal = ctx.accounts.market.proposal.ok_or(ErrorCode::NoProposal)?;
+        require!(proposal.disputer.is_none(), ErrorCode::AlreadyDisputed);
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        require!(
+            current_timestamp < proposal.dispute_deadline,
+            ErrorCode::DisputeWindowClosed
+        );
 
-#[derive(Accounts)]
-pub struct PlaceOrder<'info> {
-    #[account(
-        init,
-        payer = user,
-        space = Order::LEN,
-        seeds = [b"order", market.key().as_ref(), user.key().as_ref()],
-        bump
-    )]
-    pub order: Account<'info, Order>,
-    pub market: Account<'info, Market>,
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub system_program: Program<'info, System>,
+        post_bond(&ctx, proposal.bond)?;
+
+        let market = &mut ctx.accounts.market;
+        market.proposal = Some(Proposal {
+            disputer: Some(ctx.accounts.bonder.key()),
+            ..proposal
+        });
+
+        emit!(ResolutionDisputed {
+            market: market.key(),
+            disputer: ctx.accounts.bonder.key(),
+        });
+
+        Ok(())
+    }
+
+    /// Permissionless finalization of an undisputed proposal once the
+    /// dispute window has passed. The proposer's bond is returned.
+    pub fn finalize_resolution(ctx: Context<SettleResolution>) -> Result<()> {
+        require!(
+            !ctx.accounts.market.is_resolved,
+            ErrorCode::MarketAlreadyResolved
+        );
+        let proposal = ctx.accounts.market.proposal.ok_or(ErrorCode::NoProposal)?;
+        require!(proposal.disputer.is_none(), ErrorCode::AlreadyDisputed);
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        require!(
+            current_timestamp >= proposal.dispute_deadline,
+            ErrorCode::DisputeWindowOpen
+        );
+
+        pay_bond(&ctx, &proposal.proposer, proposal.bond)?;
+        finalize(&mut ctx.accounts.market, proposal.outcome)
+    }
+
+    /// Arbiter's ruling on a disputed proposal. Whichever side was right gets
+    /// both bonds; the other side's bond is slashed.
+    pub fn resolve_dispute(ctx: Context<SettleResolution>, outcome: Resolution) -> Result<()> {
+        outcome.validate(ctx.accounts.market.kind)?;
+        require_keys_eq!(
+            ctx.accounts.authority.key(),
+            ctx.accounts.config.arbiter,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            !ctx.accounts.market.is_resolved,
+            ErrorCode::MarketAlreadyResolved
+        );
+        let proposal = ctx.accounts.market.proposal.ok_or(ErrorCode::NoProposal)?;
+        let disputer = proposal.disputer.ok_or(ErrorCode::NotDisputed)?;
+
+        let winner = if outcome == proposal.outcome {
+            proposal.proposer
+        } else {
+            disputer
+        };
+        let bonds = proposal
+            .bond
+            .checked_mul(2)
+            .ok_or(ErrorCode::MathOverflow)?;
+        pay_bond(&ctx, &winner, bonds)?;
+        finalize(&mut ctx.accounts.market, outcome)
+    }
+
+    /// Permissionless resolution of an oracle-bound market from its feed's
+    /// current price, which must have been published at or after expiry.
+    pub fn resolve_from_oracle(ctx: Context<ResolveFromOracle>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let oracle = market.oracle.ok_or(ErrorCode::NoOracle)?;
+        require!(!market.is_resolved, ErrorCode::MarketAlreadyResolved);
+        require_keys_eq!(
+            ctx.accounts.oracle_feed.key(),
+            oracle.feed,
+            ErrorCode::InvalidOracleFeed
+        );
+
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        require!(
+            current_timestamp >= market.expiry_timestamp,
+            ErrorCode::MarketNotExpired
+        );
+
+        let outcome = oracle.resolve(
+            market.kind,
+            &ctx.accounts.oracle_feed,
+            market.expiry_timestamp,
+        )?;
+        finalize(market, outcome)
+    }
+
+    /// Voids an ill-posed or cancelled market, resolving it `Invalid` so
+    /// each YES token redeems for `yes_payout` basis points of collateral
+    /// and each NO token for the rest (half each by default). Categorical
+    /// markets always refund every outcome equally. Callable by the admin or
+    /// the arbiter; a pending proposal must be disputed and ruled on instead.
+    pub fn void_market(ctx: Context<VoidMarket>, yes_payout: Option<u64>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        let authority = ctx.accounts.authority.key();
+        require!(
+            authority == config.admin || authority == config.arbiter,
+            ErrorCode::Unauthorized
+        );
+        let market = &mut ctx.accounts.market;
+        require!(!market.is_resolved, ErrorCode::MarketAlreadyResolved);
+        require!(market.proposal.is_none(), ErrorCode::ResolutionPending);
+
+        let yes_payout = match market.kind {
+            MarketKind::Binary | MarketKind::Scalar { .. } => yes_payout.unwrap_or(PRICE_SCALE / 2),
+            MarketKind::Categorical { outcomes } => {
+                require!(yes_payout.is_none(), ErrorCode::InvalidResolution);
+                PRICE_SCALE / outcomes as u64
+            }
+        };
+        let outcome = Resolution::Invalid { yes_payout };
+        outcome.validate(market.kind)?;
+        finalize(market, outcome)
+    }
+
+    pub fn redeem(ctx: Context<Redeem>, amount: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.is_resolved, ErrorCode::MarketNotResolved);
+        let resolution = market.resolution.ok_or(ErrorCode::MarketNotResolved)?;
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let mint = ctx.accounts.outcome_mint.key();
+        let (index, side) = match market.kind {
+            MarketKind::Binary | MarketKind::Scalar { .. } => {
+                let side = market.outcome_for_mint(&mint)?;
+                (side as u8, Some(side))
+            }
+            MarketKind::Categorical { outcomes } => {
+                (market.outcome_index(&market.key(), &mint, outcomes)?, None)
+            }
+        };
+        let payout = (amount as u128 * resolution.payout(market.kind, index) as u128
+            / PRICE_SCALE as u128) as u64;
+        require!(payout > 0, ErrorCode::LosingOutcome);
+
+        token::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.outcome_mint.to_account_info(),
+                    from: ctx.accounts.user_outcome_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        // Winning tokens are worth one unit of collateral each; an invalid
+        // market splits that unit between the outcomes
+        let seeds = market.signer_seeds();
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault.to_account_info(),
+                    to: ctx.accounts.user_collateral.to_account_info(),
+                    authority: market.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            payout,
+        )?;
+
+        // Categorical supplies are tracked by their mints alone
+        let market = &mut ctx.accounts.market;
+        if let Some(side) = side {
+            let supply = match side {
+                Side::Yes => &mut market.yes_token_supply,
+                Side::No => &mut market.no_token_supply,
+            };
+            *supply = supply.checked_sub(amount).ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        emit!(Redeemed {
+            market: market.key(),
+            user: ctx.accounts.user.key(),
+            amount,
+            payout,
+        });
+
+        Ok(())
+    }
+
+    pub fn split_complete_set(ctx: Context<CompleteSet>, amount: u64) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ErrorCode::ProtocolPaused);
+
+        let market = &ctx.accounts.market;
+        require!(
+            market.is_active && !market.is_resolved,
+            ErrorCode::MarketNotActive
+        );
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_collateral.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let seeds = market.signer_seeds();
+        let signer = &[&seeds[..]];
+        for (mint, to) in [
+            (&ctx.accounts.yes_mint, &ctx.accounts.user_yes_account),
+            (&ctx.accounts.no_mint, &ctx.accounts.user_no_account),
+        ] {
+            token::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    MintTo {
+                        mint: mint.to_account_info(),
+                        to: to.to_account_info(),
+                        authority: market.to_account_info(),
+                    },
+                    signer,
+                ),
+                amount,
+            )?;
+        }
+
+        let market = &mut ctx.accounts.market;
+        market.yes_token_supply = market
+            .yes_token_supply
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        market.no_token_supply = market
+            .no_token_supply
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(CompleteSetSplit {
+            market: market.key(),
+            user: ctx.accounts.user.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    pub fn merge_complete_set(ctx: Context<CompleteSet>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        for (mint, from) in [
+            (&ctx.accounts.yes_mint, &ctx.accounts.user_yes_account),
+            (&ctx.accounts.no_mint, &ctx.accounts.user_no_account),
+        ] {
+            token::burn(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: mint.to_account_info(),
+                        from: from.to_account_info(),
+                        authority: ctx.accounts.user.to_account_info(),
+                    },
+                ),
+                amount,
+            )?;
+        }
+
+        // A YES and NO token together are always worth one unit of collateral
+        let market = &ctx.accounts.market;
+        let seeds = market.signer_seeds();
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault.to_account_info(),
+                    to: ctx.accounts.user_collateral.to_account_info(),
+                    authority: market.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+        )?;
+
+        let market = &mut ctx.accounts.market;
+        market.yes_token_supply = market
+            .yes_token_supply
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        market.no_token_supply = market
+            .no_token_supply
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(CompleteSetMerged {
+            market: market.key(),
+            user: ctx.accounts.user.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Deposits `amount` of collateral for `amount` of every outcome of a
+    /// categorical market. Remaining accounts are (outcome mint, user token
+    /// account) pairs in outcome order.
+    pub fn split_categorical_set<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CategoricalSet<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, ErrorCode::ProtocolPaused);
+
+        let market = &ctx.accounts.market;
+        require!(
+            market.is_active && !market.is_resolved,
+            ErrorCode::MarketNotActive
+        );
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        let outcome_accounts =
+            categorical_outcome_accounts(market, &ctx.accounts.user.key(), ctx.remaining_accounts)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_collateral.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let seeds = market.signer_seeds();
+        let signer = &[&seeds[..]];
+        for (mint, to) in outcome_accounts {
+            token::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    MintTo {
+                        mint: mint.to_account_info(),
+                        to: to.to_account_info(),
+                        authority: market.to_account_info(),
+                    },
+                    signer,
+                ),
+                amount,
+            )?;
+        }
+
+        emit!(CompleteSetSplit {
+            market: market.key(),
+            user: ctx.accounts.user.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Burns `amount` of every outcome of a categorical market for `amount`
+    /// of collateral, with remaining accounts as in `split_categorical_set`.
+    pub fn merge_categorical_set<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CategoricalSet<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let market = &ctx.accounts.market;
+        let outcome_accounts =
+            categorical_outcome_accounts(market, &ctx.accounts.user.key(), ctx.remaining_accounts)?;
+        for (mint, from) in outcome_accounts {
+            token::burn(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: mint.to_account_info(),
+                        from: from.to_account_info(),
+                        authority: ctx.accounts.user.to_account_info(),
+                    },
+                ),
+                amount,
+            )?;
+        }
+
+        // One token of every outcome is always worth one unit of collateral
+        let seeds = market.signer_seeds();
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault.to_account_info(),
+                    to: ctx.accounts.user_collateral.to_account_info(),
+                    authority: market.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+        )?;
+
+        emit!(CompleteSetMerged {
+            market: market.key(),
+            user: ctx.accounts.user.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+}
+
+/// Accounts needed to settle one fill between a bid and an ask.
+pub struct FillAccounts<'info> {
+    pub vault: AccountInfo<'info>,
+    pub treasury: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+/// Settles a fill that has already been taken off the book: releases both
+/// orders' locked collateral and collects fees. Outcome tokens and
+/// collateral move between the traders' internal balances in
+/// `record_fill`; depending on the directions the fill mints new complete
+/// sets, transfers outcome tokens or burns complete sets. Returns the bid's
+/// and ask's amounts.
+fn execute_fill<'info>(
+    market: &mut Account<'info, Market>,
+    config: &Config,
+    bid_order: &mut Account<'info, Order>,
+    ask_order: &mut Account<'info, Order>,
+    accounts: &FillAccounts<'info>,
+    fill: &FillEvent,
+) -> Result<(FillAmounts, FillAmounts)> {
+    let taker_side = fill.taker_side();
+    let (bid_fee_bps, ask_fee_bps) = match taker_side {
+        Side::Yes => (config.taker_fee_bps, config.maker_fee_bps),
+        Side::No => (config.maker_fee_bps, config.taker_fee_bps),
+    };
+    let bid = bid_order.apply_fill(fill.size, fill.price, bid_fee_bps)?;
+    let ask = ask_order.apply_fill(fill.size, fill.price, ask_fee_bps)?;
+
+    let total_fee = bid
+        .fee
+        .checked_add(ask.fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if total_fee > 0 {
+        let seeds = market.signer_seeds();
+        token::transfer(
+            CpiContext::new_with_signer(
+                accounts.token_program.clone(),
+                Transfer {
+                    from: accounts.vault.clone(),
+                    to: accounts.treasury.clone(),
+                    authority: market.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            total_fee,
+        )?;
+
+        let (maker_fee, taker_fee) = match taker_side {
+            Side::Yes => (ask.fee, bid.fee),
+            Side::No => (bid.fee, ask.fee),
+        };
+        emit!(FeesCollected {
+            market: market.key(),
+            bid_order: bid_order.key(),
+            ask_order: ask_order.key(),
+            maker_fee,
+            taker_fee,
+        });
+    }
+
+    let kind = FillKind::between(bid_order.direction, ask_order.direction);
+    market.record_fill_supply(kind, fill.size)?;
+
+    emit!(FillSettled {
+        bid_order: bid_order.key(),
+        ask_order: ask_order.key(),
+        fill_size: fill.size,
+        fill_price: fill.price,
+        kind,
+    });
+
+    Ok((bid, ask))
+}
+
+/// Records both sides of a fill on the traders' accounts. A self-trade
+/// passes the same trader account twice, in which case both sides are
+/// recorded on `ask_trader`, the copy that is written back last.
+fn record_fill(
+    bid_trader: &mut TraderAccount,
+    ask_trader: &mut TraderAccount,
+    bid_order: &Account<Order>,
+    ask_order: &Account<Order>,
+    fill_size: u64,
+    amounts: &(FillAmounts, FillAmounts),
+) -> Result<()> {
+    let (bid, ask) = amounts;
+    if bid_trader.owner == ask_trader.owner {
+        ask_trader.record_fill(bid_order, fill_size, bid)?;
+    } else {
+        bid_trader.record_fill(bid_order, fill_size, bid)?;
+    }
+    ask_trader.record_fill(ask_order, fill_size, ask)
+}
+
+/// Cancels `size` of a resting order that self-trade prevention took off the
+/// book while matching. Only its book entry is known there, so the order
+/// account has to be among `order_infos`.
+fn cancel_self_trade_maker<'info>(
+    order_infos: &'info [AccountInfo<'info>],
+    trader_account: &mut TraderAccount,
+    user: &AccountInfo<'info>,
+    maker: &BookEntry,
+    size: u64,
+) -> Result<()> {
+    let order_info = order_infos
+        .iter()
+        .find(|info| info.key() == maker.order)
+        .ok_or(ErrorCode::SelfTradeOrderMissing)?;
+    let mut order = Account::<Order>::try_from(order_info)?;
+    let refund = order.cancel(size)?;
+    trader_account.record_cancel(&order, size, refund)?;
+
+    emit!(OrderCancelled {
+        order_id: order.key(),
+        market: order.market,
+        user: order.user,
+        client_order_id: order.client_order_id,
+        remaining: size,
+        refund,
+    });
+
+    if order.status == OrderStatus::Cancelled {
+        order.close(user.clone())
+    } else {
+        order.exit(&crate::ID)
+    }
+}
+
+/// Moves a resolution bond from the bonder into the market vault.
+fn post_bond(ctx: &Context<ProposeResolution>, bond: u64) -> Result<()> {
+    if bond == 0 {
+        return Ok(());
+    }
+    token::transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.bonder_collateral.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
+                authority: ctx.accounts.bonder.to_account_info(),
+            },
+        ),
+        bond,
+    )
+}
+
+/// Pays `amount` of posted bonds out of the vault to `recipient`.
+fn pay_bond(ctx: &Context<SettleResolution>, recipient: &Pubkey, amount: u64) -> Result<()> {
+    require_keys_eq!(
+        ctx.accounts.recipient_collateral.owner,
+        *recipient,
+        ErrorCode::InvalidTokenAccount
+    );
+    if amount == 0 {
+        return Ok(());
+    }
+    let market = &ctx.accounts.market;
+    let seeds = market.signer_seeds();
+    token::transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.vault.to_account_info(),
+                to: ctx.accounts.recipient_collateral.to_account_info(),
+                authority: market.to_account_info(),
+            },
+            &[&seeds[..]],
+        ),
+        amount,
+    )
+}
+
+/// Marks the market resolved, which opens redemption, and settles any
+/// proposal so its bonds cannot be paid out again.
+fn finalize(market: &mut Account<Market>, outcome: Resolution) -> Result<()> {
+    market.is_resolved = true;
+    market.resolution = Some(outcome);
+    market.proposal = None;
+
+    emit!(MarketResolved {
+        market: market.key(),
+        outcome,
+    });
+
+    Ok(())
+}
+
+/// Checks that `accounts` hold an (outcome mint, token account of `user`)
+/// pair for every outcome of a categorical market, in outcome order.
+fn categorical_outcome_accounts<'info>(
+    market: &Account<Market>,
+    user: &Pubkey,
+    accounts: &'info [AccountInfo<'info>],
+) -> Result<Vec<(&'info AccountInfo<'info>, &'info AccountInfo<'info>)>> {
+    let MarketKind::Categorical { outcomes } = market.kind else {
+        return err!(ErrorCode::NotCategoricalMarket);
+    };
+    require!(
+        accounts.len() == 2 * outcomes as usize,
+        ErrorCode::InvalidOutcomeMint
+    );
+
+    let mut pairs = Vec::with_capacity(outcomes as usize);
+    for (index, pair) in (0..outcomes).zip(accounts.chunks_exact(2)) {
+        let (mint, token_account) = (&pair[0], &pair[1]);
+        require_keys_eq!(
+            mint.key(),
+            market.outcome_mint_address(&market.key(), index)?,
+            ErrorCode::InvalidOutcomeMint
+        );
+        let token_account = Account::<TokenAccount>::try_from(token_account)?;
+        require!(
+            token_account.mint == mint.key() && token_account.owner == *user,
+            ErrorCode::InvalidTokenAccount
+        );
+        pairs.push((mint, &pair[1]));
+    }
+    Ok(pairs)
+}
+
+/// Loads the trader account of `owner` in `market` from remaining accounts.
+fn load_trader_account<'info>(
+    info: &'info AccountInfo<'info>,
+    market: &Pubkey,
+    owner: &Pubkey,
+) -> Result<Account<'info, TraderAccount>> {
+    let trader_account = Account::<TraderAccount>::try_from(info)?;
+    require!(
+        trader_account.market == *market && trader_account.owner == *owner,
+        ErrorCode::InvalidTraderAccount
+    );
+    Ok(trader_account)
 }
 
 #[derive(Accounts)]
-pub struct SettleFill<'info> {
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = Config::LEN,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    pub collateral_mint: Account<'info, Mint>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::BettingExchange>,
+    /// Only the upgrade authority can claim the admin role
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ ErrorCode::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
     #[account(mut)]
-    pub buy_order: Account<'info, Order>,
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeTreasury<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub collateral_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"treasury", collateral_mint.key().as_ref()],
+        bump,
+        token::mint = collateral_mint,
+        token::authority = config
+    )]
+    pub treasury: Account<'info, TokenAccount>,
     #[account(mut)]
-    pub sell_order: Account<'info, Order>,
-    pub market: Account<'info, Market>,
-    /// CHECK: Authority for settlement operations
-    pub settlement_authority: UncheckedAccount<'info>,
+    pub admin: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    #[account(
+        mut,
+        token::authority = config
+    )]
+    pub treasury: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = treasury.mint
+    )]
+    pub destination: Account<'info, TokenAccount>,
+    pub admin: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(title: String)]
+pub struct InitializeMarket<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        init,
+        payer = creator,
+        space = Market::LEN,
+        seeds = [b"market", creator.key().as_ref(), title.as_bytes()],
+        bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+    #[account(address = config.collateral_mint @ ErrorCode::InvalidCollateralMint)]
+    pub collateral_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"vault", market.key().as_ref()],
+        bump,
+        token::mint = collateral_mint,
+        token::authority = market
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"yes_mint", market.key().as_ref()],
+        bump,
+        mint::decimals = collateral_mint.decimals,
+        mint::authority = market
+    )]
+    pub yes_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"no_mint", market.key().as_ref()],
+        bump,
+        mint::decimals = collateral_mint.decimals,
+        mint::authority = market
+    )]
+    pub no_mint: Box<Account<'info, Mint>>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(title: String)]
+pub struct InitializeCategoricalMarket<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    #[account(
+        init,
+        payer = creator,
+        space = Market::LEN,
+        seeds = [b"market", creator.key().as_ref(), title.as_bytes()],
+        bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+    #[account(address = config.collateral_mint @ ErrorCode::InvalidCollateralMint)]
+    pub collateral_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"vault", market.key().as_ref()],
+        bump,
+        token::mint = collateral_mint,
+        token::authority = market
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct ResolveMarket<'info> {
+#[instruction(index: u8)]
+pub struct InitializeOutcomeMint<'info> {
+    #[account(mut, has_one = collateral_mint)]
+    pub market: Account<'info, Market>,
+    pub collateral_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"outcome_mint", market.key().as_ref(), &[index]],
+        bump,
+        mint::decimals = collateral_mint.decimals,
+        mint::authority = market
+    )]
+    pub outcome_mint: Account<'info, Mint>,
     #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeOrderBook<'info> {
+    #[account(
+        mut,
+        has_one = creator @ ErrorCode::Unauthorized,
+        constraint = market.kind.is_two_sided() @ ErrorCode::NotBinaryMarket,
+        constraint = market.order_book == Pubkey::default()
+            && market.event_queue == Pubkey::default()
+            @ ErrorCode::OrderBookAlreadyInitialized
+    )]
     pub market: Account<'info, Market>,
+    /// Created by the client with `OrderBook::LEN` bytes and owned by this
+    /// program, since it is too large to allocate through a CPI.
+    #[account(zero)]
+    pub order_book: AccountLoader<'info, OrderBook>,
+    #[account(zero)]
+    pub event_queue: AccountLoader<'info, EventQueue>,
     pub creator: Signer<'info>,
 }
 
-#[account]
-pub struct Market {
-    pub creator: Pubkey,
-    pub title: String,
-    pub description: String,
-    pub expiry_timestamp: i64,
-    pub is_active: bool,
-    pub is_resolved: bool,
-    pub resolution: Option<bool>,
-    pub yes_token_mint: Option<Pubkey>,
-    pub no_token_mint: Option<Pubkey>,
-    pub yes_token_supply: u64,
-    pub no_token_supply: u64,
-    pub bump: u8,
+#[derive(Accounts)]
+pub struct InitializeTraderAccount<'info> {
+    pub market: Account<'info, Market>,
+    #[account(
+        init,
+        payer = user,
+        space = TraderAccount::LEN,
+        seeds = [b"trader", market.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub trader_account: Account<'info, TraderAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"trader", market.key().as_ref(), user.key().as_ref()],
+        bump = trader_account.bump
+    )]
+    pub trader_account: Account<'info, TraderAccount>,
+    #[account(
+        mut,
+        seeds = [b"vault", market.key().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = market.collateral_mint,
+        token::authority = user
+    )]
+    pub user_collateral: Account<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"trader", market.key().as_ref(), user.key().as_ref()],
+        bump = trader_account.bump
+    )]
+    pub trader_account: Account<'info, TraderAccount>,
+    #[account(
+        mut,
+        seeds = [b"vault", market.key().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = market.collateral_mint,
+        token::authority = user
+    )]
+    pub user_collateral: Account<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct TransferTokens<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"trader", market.key().as_ref(), user.key().as_ref()],
+        bump = trader_account.bump
+    )]
+    pub trader_account: Account<'info, TraderAccount>,
+    #[account(mut)]
+    pub outcome_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = outcome_mint,
+        token::authority = user
+    )]
+    pub user_outcome_account: Account<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(client_order_id: u64)]
+pub struct PlaceOrder<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = Order::LEN,
+        seeds = [
+            b"order",
+            market.key().as_ref(),
+            user.key().as_ref(),
+            &client_order_id.to_le_bytes()
+        ],
+        bump
+    )]
+    pub order: Box<Account<'info, Order>>,
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    pub market: Box<Account<'info, Market>>,
+    #[account(mut, address = market.order_book @ ErrorCode::InvalidOrderBook)]
+    pub order_book: AccountLoader<'info, OrderBook>,
+    #[account(mut, address = market.event_queue @ ErrorCode::InvalidEventQueue)]
+    pub event_queue: AccountLoader<'info, EventQueue>,
+    #[account(
+        mut,
+        seeds = [b"trader", market.key().as_ref(), user.key().as_ref()],
+        bump = trader_account.bump
+    )]
+    pub trader_account: Box<Account<'info, TraderAccount>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    #[account(
+        mut,
+        has_one = market,
+        has_one = user @ ErrorCode::Unauthorized
+    )]
+    pub order: Account<'info, Order>,
+    pub market: Account<'info, Market>,
+    #[account(mut, address = market.order_book @ ErrorCode::InvalidOrderBook)]
+    pub order_book: AccountLoader<'info, OrderBook>,
+    #[account(
+        mut,
+        seeds = [b"trader", market.key().as_ref(), user.key().as_ref()],
+        bump = trader_account.bump
+    )]
+    pub trader_account: Account<'info, TraderAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseOrder<'info> {
+    #[account(
+        mut,
+        has_one = user @ ErrorCode::Unauthorized,
+        close = user
+    )]
+    pub order: Account<'info, Order>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAll<'info> {
+    pub market: Account<'info, Market>,
+    #[account(mut, address = market.order_book @ ErrorCode::InvalidOrderBook)]
+    pub order_book: AccountLoader<'info, OrderBook>,
+    #[account(
+        mut,
+        seeds = [b"trader", market.key().as_ref(), user.key().as_ref()],
+        bump = trader_account.bump
+    )]
+    pub trader_account: Account<'info, TraderAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReplaceOrder<'info> {
+    #[account(
+        mut,
+        has_one = market,
+        has_one = user @ ErrorCode::Unauthorized
+    )]
+    pub order: Box<Account<'info, Order>>,
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    pub market: Box<Account<'info, Market>>,
+    #[account(mut, address = market.order_book @ ErrorCode::InvalidOrderBook)]
+    pub order_book: AccountLoader<'info, OrderBook>,
+    #[account(mut, address = market.event_queue @ ErrorCode::InvalidEventQueue)]
+    pub event_queue: AccountLoader<'info, EventQueue>,
+    #[account(
+        mut,
+        seeds = [b"trader", market.key().as_ref(), user.key().as_ref()],
+        bump = trader_account.bump
+    )]
+    pub trader_account: Box<Account<'info, TraderAccount>>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PruneExpiredOrders<'info> {
+    pub market: Account<'info, Market>,
+    #[account(mut, address = market.order_book @ ErrorCode::InvalidOrderBook)]
+    pub order_book: AccountLoader<'info, OrderBook>,
+}
+
+#[derive(Accounts)]
+pub struct ConsumeEvents<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    #[account(mut)]
+    pub market: Box<Account<'info, Market>>,
+    #[account(mut, address = market.event_queue @ ErrorCode::InvalidEventQueue)]
+    pub event_queue: AccountLoader<'info, EventQueue>,
+    #[account(
+        mut,
+        seeds = [b"vault", market.key().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        token::mint = market.collateral_mint,
+        token::authority = config
+    )]
+    pub treasury: Box<Account<'info, TokenAccount>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeResolution<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"vault", market.key().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = market.collateral_mint,
+        token::authority = bonder
+    )]
+    pub bonder_collateral: Account<'info, TokenAccount>,
+    pub bonder: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct VoidMarket<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleResolution<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"vault", market.key().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    /// Collateral account of whoever the bonds are paid to
+    #[account(mut, token::mint = market.collateral_mint)]
+    pub recipient_collateral: Account<'info, TokenAccount>,
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveFromOracle<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    /// CHECK: must be the market's oracle feed; its owner and layout are
+    /// checked by its source's adapter
+    pub oracle_feed: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Redeem<'info> {
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"vault", market.key().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub outcome_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = outcome_mint,
+        token::authority = user
+    )]
+    pub user_outcome_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = market.collateral_mint,
+        token::authority = user
+    )]
+    pub user_collateral: Account<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteSet<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"vault", market.key().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = market.yes_token_mint == Some(yes_mint.key()) @ ErrorCode::InvalidOutcomeMint
+    )]
+    pub yes_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = market.no_token_mint == Some(no_mint.key()) @ ErrorCode::InvalidOutcomeMint
+    )]
+    pub no_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = yes_mint,
+        token::authority = user
+    )]
+    pub user_yes_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = no_mint,
+        token::authority = user
+    )]
+    pub user_no_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = market.collateral_mint,
+        token::authority = user
+    )]
+    pub user_collateral: Account<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CategoricalSet<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"vault", market.key().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = market.collateral_mint,
+        token::authority = user
+    )]
+    pub user_collateral: Account<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[account]
+pub struct Config {
+    pub admin: Pubkey,
+    pub collateral_mint: Pubkey,
+    pub maker_fee_bps: u16,
+    pub taker_fee_bps: u16,
+    pub min_order_size: u64,  // keeps dust orders from filling the book
+    pub arbiter: Pubkey,      // decides disputed resolutions
+    pub resolution_bond: u64, // posted by proposers and disputers
+    pub dispute_window: i64,  // seconds a proposal can be disputed
+    pub paused: bool,
+    pub bump: u8,
+}
+
+impl Config {
+    pub const LEN: usize = 8 + 32 + 32 + 2 + 2 + 8 + 32 + 8 + 8 + 1 + 1;
+}
+
+#[account]
+pub struct Market {
+    pub creator: Pubkey,
+    pub title: String,
+    pub description: String,
+    pub expiry_timestamp: i64,
+    pub kind: MarketKind,
+    pub is_active: bool,
+    pub is_resolved: bool,
+    pub resolution: Option<Resolution>,
+    pub oracle: Option<OracleConfig>,
+    pub proposal: Option<Proposal>,
+    pub yes_token_mint: Option<Pubkey>,
+    pub no_token_mint: Option<Pubkey>,
+    pub yes_token_supply: u64,
+    pub no_token_supply: u64,
+    pub collateral_mint: Pubkey,
+    pub order_book: Pubkey,
+    pub event_queue: Pubkey,
+    pub bump: u8,
+    pub vault_bump: u8,
+    pub outcome_mint_bumps: [u8; MAX_OUTCOMES as usize], // categorical markets only
+}
+
+impl Market {
+    /// Titles are used as a PDA seed, which caps them at 32 bytes.
+    pub const MAX_TITLE_LEN: usize = 32;
+    pub const MAX_DESCRIPTION_LEN: usize = 512;
+
+    pub const LEN: usize = 8
+        + 32
+        + (4 + Self::MAX_TITLE_LEN)
+        + (4 + Self::MAX_DESCRIPTION_LEN)
+        + 8
+        + MarketKind::LEN
+        + 1
+        + 1
+        + (1 + Resolution::LEN)
+        + (1 + OracleConfig::LEN)
+        + (1 + Proposal::LEN)
+        + 33
+        + 33
+        + 8
+        + 8
+        + 32
+        + 32
+        + 32
+        + 1
+        + 1
+        + MAX_OUTCOMES as usize;
+
+    /// Updates the outcome token supplies for a fill of `size` that mints or
+    /// burns complete sets.
+    pub fn record_fill_supply(&mut self, kind: FillKind, size: u64) -> Result<()> {
+        let (yes, no) = match kind {
+            FillKind::Mint => (
+                self.yes_token_supply.checked_add(size),
+                self.no_token_supply.checked_add(size),
+            ),
+            FillKind::Burn => (
+                self.yes_token_supply.checked_sub(size),
+                self.no_token_supply.checked_sub(size),
+            ),
+            FillKind::Transfer => return Ok(()),
+        };
+        self.yes_token_supply = yes.ok_or(ErrorCode::MathOverflow)?;
+        self.no_token_supply = no.ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Outcome whose token is minted by `mint`.
+    pub fn outcome_for_mint(&self, mint: &Pubkey) -> Result<Side> {
+        if self.yes_token_mint == Some(*mint) {
+            Ok(Side::Yes)
+        } else if self.no_token_mint == Some(*mint) {
+            Ok(Side::No)
+        } else {
+            err!(ErrorCode::InvalidOutcomeMint)
+        }
+    }
+
+    /// Address of the mint for outcome `index` of this categorical market,
+    /// derived from its stored bump rather than searched for.
+    pub fn outcome_mint_address(&self, market: &Pubkey, index: u8) -> Result<Pubkey> {
+        let bump = self
+            .outcome_mint_bumps
+            .get(index as usize)
+            .ok_or(ErrorCode::InvalidOutcomeIndex)?;
+        Pubkey::create_program_address(
+            &[b"outcome_mint", market.as_ref(), &[index], &[*bump]],
+            &crate::ID,
+        )
+        .map_err(|_| error!(ErrorCode::InvalidOutcomeMint))
+    }
+
+    /// Index of the categorical outcome minted by `mint`.
+    pub fn outcome_index(&self, market: &Pubkey, mint: &Pubkey, outcomes: u8) -> Result<u8> {
+        (0..outcomes)
+            .find(|&index| self.outcome_mint_address(market, index).ok() == Some(*mint))
+            .ok_or(error!(ErrorCode::InvalidOutcomeMint))
+    }
+
+    /// Seeds for signing as the market PDA, which owns the vault and both
+    /// outcome mints.
+    pub fn signer_seeds(&self) -> [&[u8]; 4] {
+        [
+            b"market",
+            self.creator.as_ref(),
+            self.title.as_bytes(),
+            std::slice::from_ref(&self.bump),
+        ]
+    }
+}
+
+/// Final outcome of a market.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Yes,
+    No,
+    /// Winning outcome of a categorical market.
+    Outcome {
+        index: u8,
+    },
+    /// Resolved value of a scalar market, paying LONG linearly across the
+    /// market's range and SHORT the rest.
+    Scalar {
+        value: i64,
+    },
+    /// The market was ill-posed or cancelled; YES tokens redeem for
+    /// `yes_payout` basis points of collateral and NO tokens for the rest.
+    /// Every outcome of a categorical market redeems for `yes_payout`.
+    Invalid {
+        yes_payout: u64,
+    },
+}
+
+impl Resolution {
+    pub const LEN: usize = 1 + 8;
+
+    /// Checks the resolution applies to a market of `kind` and pays out no
+    /// more than a complete set is worth.
+    pub fn validate(&self, kind: MarketKind) -> Result<()> {
+        let valid = match (*self, kind) {
+            (Resolution::Yes | Resolution::No, MarketKind::Binary) => true,
+            (Resolution::Outcome { index }, MarketKind::Categorical { outcomes }) => {
+                index < outcomes
+            }
+            (Resolution::Scalar { .. }, MarketKind::Scalar { .. }) => true,
+            (
+                Resolution::Invalid { yes_payout },
+                MarketKind::Binary | MarketKind::Scalar { .. },
+            ) => yes_payout <= PRICE_SCALE,
+            (Resolution::Invalid { yes_payout }, MarketKind::Categorical { outcomes }) => {
+                yes_payout == PRICE_SCALE / outcomes as u64
+            }
+            _ => false,
+        };
+        require!(valid, ErrorCode::InvalidResolution);
+        Ok(())
+    }
+
+    /// Collateral paid per token of outcome `index`, in basis points.
+    /// Binary and scalar markets number YES (LONG) 0 and NO (SHORT) 1.
+    pub fn payout(&self, kind: MarketKind, index: u8) -> u64 {
+        let yes_payout = match (*self, kind) {
+            (Resolution::Invalid { yes_payout }, MarketKind::Categorical { .. }) => {
+                return yes_payout
+            }
+            (Resolution::Invalid { yes_payout }, _) => yes_payout,
+            (Resolution::Scalar { value }, MarketKind::Scalar { lower, upper }) => {
+                let offset = value.clamp(lower, upper) as i128 - lower as i128;
+                let range = upper as i128 - lower as i128;
+                (offset * PRICE_SCALE as i128 / range) as u64
+            }
+            (Resolution::Outcome { index: winner }, _) => {
+                return if index == winner { PRICE_SCALE } else { 0 };
+            }
+            (Resolution::Yes, _) => PRICE_SCALE,
+            _ => 0,
+        };
+        match index {
+            0 => yes_payout,
+            _ => PRICE_SCALE - yes_payout,
+        }
+    }
+}
+
+/// A proposed resolution, final once its dispute window passes undisputed.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Proposal {
+    pub proposer: Pubkey,
+    pub outcome: Resolution,
+    pub proposed_at: i64,
+    pub dispute_deadline: i64, // fixed when proposed, like the bond
+    pub bond: u64,             // matched by the disputer, if any
+    pub disputer: Option<Pubkey>,
+}
+
+impl Proposal {
+    pub const LEN: usize = 32 + Resolution::LEN + 8 + 8 + 8 + (1 + 32);
+}
+
+/// Binds a market's resolution to an oracle feed: the market resolves YES
+/// when the feed's price compares to `threshold * 10^threshold_expo` as
+/// `comparator` says, NO otherwise. Only a price published within
+/// `max_delay` seconds of expiry, with a confidence interval no wider than
+/// `max_confidence_bps` of the price, can resolve the market; if the feed
+/// publishes none, the market has to be voided.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct OracleConfig {
+    pub source: OracleSource,
+    pub feed: Pubkey,
+    pub threshold: i64,
+    pub threshold_expo: i32,
+    pub comparator: Comparator,
+    pub max_delay: i64,
+    pub max_confidence_bps: u16,
+}
+
+impl OracleConfig {
+    pub const LEN: usize = 1 + 32 + 8 + 4 + 1 + 8 + 2;
+
+    /// Largest exponent magnitude accepted from a config or a feed, which
+    /// keeps rescaling between the two within `i128`.
+    pub const MAX_EXPO: i32 = 32;
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            (-Self::MAX_EXPO..=Self::MAX_EXPO).contains(&self.threshold_expo)
+                && self.max_delay > 0
+                && self.max_confidence_bps as u64 <= PRICE_SCALE,
+            ErrorCode::InvalidOracleConfig
+        );
+        Ok(())
+    }
+
+    /// Whether `price` resolves the market YES.
+    pub fn evaluate(&self, price: &OraclePrice) -> Result<bool> {
+        // Compare both values at the smaller of the two exponents
+        let expo = price.expo.min(self.threshold_expo);
+        let scale = |value: i64, from: i32| -> Result<i128> {
+            10i128
+                .checked_pow((from - expo) as u32)
+                .and_then(|factor| (value as i128).checked_mul(factor))
+                .ok_or(error!(ErrorCode::MathOverflow))
+        };
+        let value = scale(price.price, price.expo)?;
+        let threshold = scale(self.threshold, self.threshold_expo)?;
+
+        Ok(match self.comparator {
+            Comparator::GreaterThan => value > threshold,
+            Comparator::GreaterThanOrEqual => value >= threshold,
+            Comparator::LessThan => value < threshold,
+            Comparator::LessThanOrEqual => value <= threshold,
+        })
+    }
+
+    /// Resolution of a market of `kind` expiring at `expiry` from the price
+    /// in `feed`, which must have been published at or after expiry and
+    /// within `max_delay` of it, with a narrow enough confidence interval.
+    pub fn resolve(&self, kind: MarketKind, feed: &AccountInfo, expiry: i64) -> Result<Resolution> {
+        let price = self.source.read_price(feed)?;
+        require!(price.publish_time >= expiry, ErrorCode::StaleOraclePrice);
+        let deadline = expiry
+            .checked_add(self.max_delay)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(price.publish_time <= deadline, ErrorCode::LateOraclePrice);
+        require!(
+            price.conf as u128 * PRICE_SCALE as u128
+                <= price.price.unsigned_abs() as u128 * self.max_confidence_bps as u128,
+            ErrorCode::OracleConfidenceTooWide
+        );
+        Ok(match kind {
+            MarketKind::Scalar { .. } => Resolution::Scalar {
+                value: self.value(&price)?,
+            },
+            _ if self.evaluate(&price)? => Resolution::Yes,
+            _ => Resolution::No,
+        })
+    }
+
+    /// `price` quoted at `threshold_expo`, rounded toward zero, which is
+    /// what a scalar market resolves to.
+    pub fn value(&self, price: &OraclePrice) -> Result<i64> {
+        let shift = price.expo - self.threshold_expo;
+        let factor = 10i128
+            .checked_pow(shift.unsigned_abs())
+            .ok_or(ErrorCode::MathOverflow)?;
+        let value = if shift >= 0 {
+            (price.price as i128)
+                .checked_mul(factor)
+                .ok_or(ErrorCode::MathOverflow)?
+        } else {
+            price.price as i128 / factor
+        };
+        i64::try_from(value).map_err(|_| error!(ErrorCode::MathOverflow))
+    }
+}
+
+/// A price read from an oracle feed, worth `price * 10^expo`, give or take
+/// `conf` at the same exponent.
+pub struct OraclePrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+impl OracleSource {
+    /// Program that owns this source's feed accounts.
+    pub fn program_id(self) -> Pubkey {
+        match self {
+            OracleSource::Pyth => pyth::PROGRAM_ID,
+            OracleSource::Switchboard => switchboard::PROGRAM_ID,
+        }
+    }
+
+    /// Reads the current price from a feed account laid out for this
+    /// source. Feeds written by the mock oracle are only trusted in builds
+    /// with the `mock-oracle` feature.
+    pub fn read_price(self, feed: &AccountInfo) -> Result<OraclePrice> {
+        let mock = cfg!(any(test, feature = "mock-oracle")) && *feed.owner == MOCK_ORACLE_ID;
+        require!(
+            *feed.owner == self.program_id() || mock,
+            ErrorCode::InvalidOracleFeed
+        );
+
+        let data = feed.try_borrow_data()?;
+        let price = match self {
+            OracleSource::Pyth => read_pyth_price(&data)?,
+            OracleSource::Switchboard => read_switchboard_price(&data)?,
+        };
+        require!(
+            (-OracleConfig::MAX_EXPO..=OracleConfig::MAX_EXPO).contains(&price.expo),
+            ErrorCode::InvalidOracleData
+        );
+        Ok(price)
+    }
+}
+
+/// Program of the local mock oracle, whose feeds anyone can write.
+const MOCK_ORACLE_ID: Pubkey = pubkey!("GZsvz4beFEzLraueUpSRZ4sFe59irrbw6ecKbo2hVVKL");
+
+/// Pyth price account (v2 layout): header, exponent, publish timestamp and
+/// the aggregate price, which is only usable while its status is trading.
+mod pyth {
+    use anchor_lang::prelude::*;
+
+    pub const PROGRAM_ID: Pubkey = pubkey!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
+
+    pub const MAGIC: u32 = 0xa1b2_c3d4;
+    pub const VERSION: u32 = 2;
+    pub const PRICE_ACCOUNT: u32 = 3;
+    pub const STATUS_TRADING: u32 = 1;
+
+    pub const MAGIC_OFFSET: usize = 0;
+    pub const VERSION_OFFSET: usize = 4;
+    pub const ACCOUNT_TYPE_OFFSET: usize = 8;
+    pub const EXPO_OFFSET: usize = 20;
+    pub const TIMESTAMP_OFFSET: usize = 96;
+    pub const AGG_PRICE_OFFSET: usize = 208;
+    pub const AGG_CONF_OFFSET: usize = 216;
+    pub const AGG_STATUS_OFFSET: usize = 224;
+    pub const LEN: usize = 240;
+}
+
+/// Switchboard v2 aggregator account: the result of the latest confirmed
+/// round, a decimal `mantissa / 10^scale`, and the standard deviation of
+/// the oracle responses behind it.
+mod switchboard {
+    use anchor_lang::prelude::*;
+
+    pub const PROGRAM_ID: Pubkey = pubkey!("SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f");
+
+    pub const DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+
+    pub const NUM_SUCCESS_OFFSET: usize = 341;
+    pub const ROUND_OPEN_TIMESTAMP_OFFSET: usize = 358;
+    pub const MANTISSA_OFFSET: usize = 366;
+    pub const SCALE_OFFSET: usize = 382;
+    pub const STD_DEVIATION_MANTISSA_OFFSET: usize = 386;
+    pub const STD_DEVIATION_SCALE_OFFSET: usize = 402;
+    pub const LEN: usize = 406;
+}
+
+fn read_pyth_price(data: &[u8]) -> Result<OraclePrice> {
+    require!(data.len() >= pyth::LEN, ErrorCode::InvalidOracleData);
+    require!(
+        read_u32(data, pyth::MAGIC_OFFSET) == pyth::MAGIC
+            && read_u32(data, pyth::VERSION_OFFSET) == pyth::VERSION
+            && read_u32(data, pyth::ACCOUNT_TYPE_OFFSET) == pyth::PRICE_ACCOUNT,
+        ErrorCode::InvalidOracleData
+    );
+    require!(
+        read_u32(data, pyth::AGG_STATUS_OFFSET) == pyth::STATUS_TRADING,
+        ErrorCode::InvalidOracleData
+    );
+
+    Ok(OraclePrice {
+        price: read_i64(data, pyth::AGG_PRICE_OFFSET),
+        conf: read_i64(data, pyth::AGG_CONF_OFFSET) as u64,
+        expo: read_u32(data, pyth::EXPO_OFFSET) as i32,
+        publish_time: read_i64(data, pyth::TIMESTAMP_OFFSET),
+    })
+}
+
+fn read_switchboard_price(data: &[u8]) -> Result<OraclePrice> {
+    require!(
+        data.len() >= switchboard::LEN && data[..8] == switchboard::DISCRIMINATOR,
+        ErrorCode::InvalidOracleData
+    );
+    require!(
+        read_u32(data, switchboard::NUM_SUCCESS_OFFSET) > 0,
+        ErrorCode::InvalidOracleData
+    );
+
+    let mantissa = read_i128(data, switchboard::MANTISSA_OFFSET);
+    let scale = read_u32(data, switchboard::SCALE_OFFSET);
+
+    // Quote the deviation at the result's scale, rounding up
+    let deviation = read_i128(data, switchboard::STD_DEVIATION_MANTISSA_OFFSET);
+    let deviation_scale = read_u32(data, switchboard::STD_DEVIATION_SCALE_OFFSET);
+    require!(deviation >= 0, ErrorCode::InvalidOracleData);
+    let factor = 10i128
+        .checked_pow(scale.abs_diff(deviation_scale))
+        .ok_or(ErrorCode::InvalidOracleData)?;
+    let conf = if deviation_scale <= scale {
+        deviation
+            .checked_mul(factor)
+            .ok_or(ErrorCode::InvalidOracleData)?
+    } else {
+        deviation / factor + (deviation % factor != 0) as i128
+    };
+
+    Ok(OraclePrice {
+        price: i64::try_from(mantissa).map_err(|_| error!(ErrorCode::InvalidOracleData))?,
+        conf: u64::try_from(conf).map_err(|_| error!(ErrorCode::InvalidOracleData))?,
+        expo: -i32::try_from(scale).map_err(|_| error!(ErrorCode::InvalidOracleData))?,
+        publish_time: read_i64(data, switchboard::ROUND_OPEN_TIMESTAMP_OFFSET),
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_i64(data: &[u8], offset: usize) -> i64 {
+    i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_i128(data: &[u8], offset: usize) -> i128 {
+    i128::from_le_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+#[account]
+pub struct Order {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub client_order_id: u64,
+    pub side: Side, // outcome traded
+    pub direction: Direction,
+    pub order_type: OrderType,
+    pub self_trade_prevention: SelfTradePrevention,
+    pub price: u64, // in basis points, YES terms
+    pub size: u64,
+    pub filled: u64,
+    pub status: OrderStatus,
+    pub fee_bps: u16, // fee rate reserved for at placement
+    pub locked: u64,  // collateral still held in the vault for this order
+    pub expires_at: Option<i64>,
+    pub bump: u8,
+}
+
+impl Order {
+    pub const LEN: usize = 8 + 32 + 32 + 8 + 1 + 1 + 1 + 1 + 8 + 8 + 8 + 1 + 2 + 8 + 9 + 1;
+
+    /// Book side the order rests on: orders gaining YES exposure are bids
+    /// (`Side::Yes`), orders gaining NO exposure are asks (`Side::No`).
+    pub fn book_side(&self) -> Side {
+        match self.direction {
+            Direction::Buy => self.side,
+            Direction::Sell => self.side.opposite(),
+        }
+    }
+
+    /// Collateral value of `size` of an outcome at `price`: the price
+    /// itself for YES, the complement for NO.
+    pub fn collateral_for(side: Side, price: u64, size: u64) -> Result<u64> {
+        require!(price <= PRICE_SCALE, ErrorCode::InvalidPrice);
+        let unit_price = match side {
+            Side::Yes => price,
+            Side::No => PRICE_SCALE - price,
+        };
+        let collateral = (unit_price as u128)
+            .checked_mul(size as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / PRICE_SCALE as u128;
+        u64::try_from(collateral).map_err(|_| error!(ErrorCode::MathOverflow))
+    }
+
+    /// Fee charged on `amount` of collateral at `fee_bps`, rounded down.
+    pub fn fee_on(amount: u64, fee_bps: u16) -> Result<u64> {
+        let fee = (amount as u128)
+            .checked_mul(fee_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / PRICE_SCALE as u128;
+        u64::try_from(fee).map_err(|_| error!(ErrorCode::MathOverflow))
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Cancels `remaining` size that was taken off the book and returns the
+    /// collateral to refund. Fills already matched keep their collateral
+    /// locked, so the order is only marked cancelled once none are pending.
+    pub fn cancel(&mut self, remaining: u64) -> Result<u64> {
+        self.size = self
+            .size
+            .checked_sub(remaining)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let settled = self.filled == self.size;
+        let refund = if settled {
+            self.locked
+        } else {
+            self.reserved_for(remaining)?
+        };
+        self.locked = self
+            .locked
+            .checked_sub(refund)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if settled {
+            self.status = OrderStatus::Cancelled;
+        }
+        Ok(refund)
+    }
+
+    /// Collateral locked at placement for `size` of this order: its cost at
+    /// the limit price plus the fee reserve. Sell orders lock outcome tokens
+    /// instead and pay their fee out of the proceeds.
+    pub fn reserved_for(&self, size: u64) -> Result<u64> {
+        if self.direction == Direction::Sell {
+            return Ok(0);
+        }
+        let cost = Self::collateral_for(self.side, self.price, size)?;
+        cost.checked_add(Self::fee_on(cost, self.fee_bps)?)
+            .ok_or(error!(ErrorCode::MathOverflow))
+    }
+
+    /// Records a fill of `fill_size` at `fill_price` and releases the
+    /// collateral locked behind it. A buy pays its cost at the fill price
+    /// and the fee at `fee_bps` (capped at the rate reserved for) out of the
+    /// release and is credited the rest; a sell is credited its proceeds
+    /// less the fee.
+    pub fn apply_fill(
+        &mut self,
+        fill_size: u64,
+        fill_price: u64,
+        fee_bps: u16,
+    ) -> Result<FillAmounts> {
+        self.filled = self
+            .filled
+            .checked_add(fill_size)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let released = if self.filled >= self.size {
+            self.status = OrderStatus::Filled;
+            self.locked
+        } else {
+            self.status = OrderStatus::Partial;
+            self.reserved_for(fill_size)?
+        };
+        self.locked = self
+            .locked
+            .checked_sub(released)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let value = Self::collateral_for(self.side, fill_price, fill_size)?;
+        let fee = Self::fee_on(value, fee_bps.min(self.fee_bps))?;
+        let credited = match self.direction {
+            Direction::Buy => released
+                .checked_sub(value)
+                .and_then(|rest| rest.checked_sub(fee)),
+            Direction::Sell => value.checked_sub(fee),
+        }
+        .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(FillAmounts {
+            released,
+            fee,
+            credited,
+        })
+    }
+}
+
+/// Collateral movements resulting from one side of a fill.
+pub struct FillAmounts {
+    pub released: u64, // taken out of the order's locked collateral
+    pub fee: u64,
+    pub credited: u64, // paid into the trader's free balance
+}
+
+/// Per-user state in one market: the user's open order slots, the
+/// collateral and outcome tokens the program holds for them, and the PnL
+/// realized on selling outcome tokens against what they cost on average.
+#[account]
+pub struct TraderAccount {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub open_orders: Vec<Pubkey>,
+    pub free_collateral: u64,   // held in the vault, not backing any order
+    pub locked_collateral: u64, // sum of `locked` over the open orders
+    pub yes_position: u64,      // outcome tokens not offered in sell orders
+    pub no_position: u64,
+    pub locked_yes: u64, // outcome tokens offered in open sell orders
+    pub locked_no: u64,
+    pub yes_cost_basis: u64, // collateral paid for the YES tokens held, free and locked
+    pub no_cost_basis: u64,
+    pub realized_pnl: i64, // sale proceeds less the cost basis of the tokens sold
+    pub bump: u8,
+}
+
+impl TraderAccount {
+    pub const MAX_OPEN_ORDERS: usize = 32;
+
+    pub const LEN: usize =
+        8 + 32 + 32 + (4 + 32 * Self::MAX_OPEN_ORDERS) + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+
+    /// Takes a slot for a newly placed order and locks its collateral out of
+    /// the free balance.
+    pub fn open_order(&mut self, order: Pubkey, locked: u64) -> Result<()> {
+        require!(
+            self.open_orders.len() < Self::MAX_OPEN_ORDERS,
+            ErrorCode::TooManyOpenOrders
+        );
+        self.open_orders.push(order);
+        self.lock(locked)
+    }
+
+    pub fn deposit(&mut self, amount: u64) -> Result<()> {
+        self.free_collateral = self
+            .free_collateral
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn withdraw(&mut self, amount: u64) -> Result<()> {
+        self.free_collateral = self
+            .free_collateral
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientBalance)?;
+        Ok(())
+    }
+
+    /// Moves `amount` from the free balance into order collateral.
+    pub fn lock(&mut self, amount: u64) -> Result<()> {
+        self.withdraw(amount)?;
+        self.locked_collateral = self
+            .locked_collateral
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Returns `amount` of order collateral to the free balance.
+    pub fn unlock(&mut self, amount: u64) -> Result<()> {
+        self.locked_collateral = self
+            .locked_collateral
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.deposit(amount)
+    }
+
+    /// Free and locked balances of one outcome token.
+    fn position_mut(&mut self, outcome: Side) -> (&mut u64, &mut u64) {
+        match outcome {
+            Side::Yes => (&mut self.yes_position, &mut self.locked_yes),
+            Side::No => (&mut self.no_position, &mut self.locked_no),
+        }
+    }
+
+    fn cost_basis_mut(&mut self, outcome: Side) -> &mut u64 {
+        match outcome {
+            Side::Yes => &mut self.yes_cost_basis,
+            Side::No => &mut self.no_cost_basis,
+        }
+    }
+
+    /// Takes the average cost of `amount` tokens of `outcome` off its cost
+    /// basis, before they leave the position, and returns it.
+    fn release_cost_basis(&mut self, outcome: Side, amount: u64) -> u64 {
+        let (free, locked) = self.position_mut(outcome);
+        let held = *free as u128 + *locked as u128;
+        let basis = self.cost_basis_mut(outcome);
+        let released = if amount as u128 >= held {
+            *basis
+        } else {
+            (*basis as u128 * amount as u128 / held) as u64
+        };
+        *basis -= released;
+        released
+    }
+
+    /// Deposited tokens were acquired outside the exchange, so they add to
+    /// the position at no cost basis.
+    pub fn deposit_tokens(&mut self, outcome: Side, amount: u64) -> Result<()> {
+        let (free, _) = self.position_mut(outcome);
+        *free = free.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Withdrawn tokens take their average cost with them without realizing
+    /// any PnL.
+    pub fn withdraw_tokens(&mut self, outcome: Side, amount: u64) -> Result<()> {
+        let (free, _) = self.position_mut(outcome);
+        require!(amount <= *free, ErrorCode::InsufficientBalance);
+        self.release_cost_basis(outcome, amount);
+        let (free, _) = self.position_mut(outcome);
+        *free -= amount;
+        Ok(())
+    }
+
+    /// Moves `amount` outcome tokens from the free position into a sell
+    /// order.
+    pub fn lock_tokens(&mut self, outcome: Side, amount: u64) -> Result<()> {
+        let (free, locked) = self.position_mut(outcome);
+        *free = free
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientBalance)?;
+        *locked = locked.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Returns `amount` outcome tokens from a sell order to the free
+    /// position.
+    pub fn unlock_tokens(&mut self, outcome: Side, amount: u64) -> Result<()> {
+        let (free, locked) = self.position_mut(outcome);
+        *locked = locked.checked_sub(amount).ok_or(ErrorCode::MathOverflow)?;
+        *free = free.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Records the cancellation of `size` of an order: the refunded
+    /// collateral of a buy or the outcome tokens of a sell go back to the
+    /// free balances.
+    pub fn record_cancel(&mut self, order: &Account<Order>, size: u64, refund: u64) -> Result<()> {
+        self.unlock(refund)?;
+        if order.direction == Direction::Sell {
+            self.unlock_tokens(order.side, size)?;
+        }
+        self.sync_order(order);
+        Ok(())
+    }
+
+    /// Frees the order's slot once it can no longer trade or be settled.
+    pub fn sync_order(&mut self, order: &Account<Order>) {
+        if order.status == OrderStatus::Filled || order.status == OrderStatus::Cancelled {
+            let key = order.key();
+            self.open_orders.retain(|open| *open != key);
+        }
+    }
+
+    /// Records one side of a settled fill: spends what the order released
+    /// and pays the credit into the free balance. A buy receives the outcome
+    /// tokens and adds what it paid, fee included, to their cost basis; a
+    /// sell hands over the ones it locked and realizes its proceeds, net of
+    /// the fee, less their average cost.
+    pub fn record_fill(
+        &mut self,
+        order: &Account<Order>,
+        fill_size: u64,
+        amounts: &FillAmounts,
+    ) -> Result<()> {
+        self.locked_collateral = self
+            .locked_collateral
+            .checked_sub(amounts.released)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.deposit(amounts.credited)?;
+
+        match order.direction {
+            Direction::Buy => {
+                let cost = amounts
+                    .released
+                    .checked_sub(amounts.credited)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                let basis = self.cost_basis_mut(order.side);
+                *basis = basis.checked_add(cost).ok_or(ErrorCode::MathOverflow)?;
+                let (free, _) = self.position_mut(order.side);
+                *free = free.checked_add(fill_size).ok_or(ErrorCode::MathOverflow)?;
+            }
+            Direction::Sell => {
+                let cost = self.release_cost_basis(order.side, fill_size);
+                let pnl = i64::try_from(amounts.credited as i128 - cost as i128)
+                    .map_err(|_| error!(ErrorCode::MathOverflow))?;
+                self.realized_pnl = self
+                    .realized_pnl
+                    .checked_add(pnl)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                let (_, locked) = self.position_mut(order.side);
+                *locked = locked
+                    .checked_sub(fill_size)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
+
+        self.sync_order(order);
+        Ok(())
+    }
+}
+
+/// Resting orders for a market. YES buys and NO sells rest as bids, YES
+/// sells and NO buys as asks, all priced in YES terms, each side kept in
+/// price-time priority with the best order first.
+#[account(zero_copy)]
+pub struct OrderBook {
+    pub market: Pubkey,
+    pub bids: BookSide,
+    pub asks: BookSide,
+}
+
+impl OrderBook {
+    pub const LEN: usize = 8 + std::mem::size_of::<OrderBook>();
+
+    pub fn insert(&mut self, side: Side, entry: BookEntry) -> Result<()> {
+        match side {
+            Side::Yes => self
+                .bids
+                .insert(entry, |resting| resting.price >= entry.price),
+            Side::No => self
+                .asks
+                .insert(entry, |resting| resting.price <= entry.price),
+        }
+    }
+
+    /// Whether an order at `price` on `side` would match a resting order
+    /// that has not expired by `now`.
+    pub fn would_cross(&self, side: Side, price: u64, now: i64) -> bool {
+        self.side(side.opposite())
+            .entries()
+            .iter()
+            .find(|resting| !resting.is_expired(now))
+            .is_some_and(|best| side.crosses(price, best.price))
+    }
+
+    pub fn get(&self, side: Side, order: &Pubkey) -> Option<BookEntry> {
+        let book_side = self.side(side);
+        book_side.find(order).map(|index| book_side.entries[index])
+    }
+
+    pub fn remove(&mut self, side: Side, order: &Pubkey) -> Option<BookEntry> {
+        self.side_mut(side).remove(order)
+    }
+
+    /// Crosses `size` of an incoming `taker` order against the opposite side
+    /// of the book, best price first, skipping resting orders that expired
+    /// before `now`. `on_fill` is called with each resting order matched and
+    /// the size taken from it. Resting orders of the taker's own user are not
+    /// matched; the taker's self-trade prevention mode decides how much of
+    /// each side is cancelled instead, and `on_self_trade` is called with
+    /// the size cancelled from the resting order. After
+    /// `MAX_MATCHES_PER_ORDER` live resting orders or
+    /// `MAX_EXPIRED_SKIPS_PER_ORDER` expired ones, whatever still crosses the
+    /// book is cancelled from the taker too, as it cannot rest.
+    pub fn match_order(
+        &mut self,
+        taker: &Order,
+        size: u64,
+        now: i64,
+        mut on_fill: impl FnMut(&BookEntry, u64) -> Result<()>,
+        mut on_self_trade: impl FnMut(&BookEntry, u64) -> Result<()>,
+    ) -> Result<MatchResult> {
+        let side = taker.book_side();
+        let maker_side = side.opposite();
+        let mut remaining = size;
+        let mut cancelled = 0;
+        let mut limit_reached = false;
+        let mut index = 0;
+        let mut matches = 0;
+        let mut skipped = 0;
+
+        while remaining > 0 {
+            let Some(resting) = self.side(maker_side).entries().get(index).copied() else {
+                break;
+            };
+            if !side.crosses(taker.price, resting.price) {
+                break;
+            }
+            let expired = resting.is_expired(now);
+            if expired && skipped == MAX_EXPIRED_SKIPS_PER_ORDER
+                || !expired && matches == MAX_MATCHES_PER_ORDER
+            {
+                cancelled += remaining;
+                remaining = 0;
+                limit_reached = true;
+                break;
+            }
+            if expired {
+                skipped += 1;
+                index += 1;
+                continue;
+            }
+            matches += 1;
+
+            if resting.owner == taker.user {
+                let (taker_cancelled, maker_cancelled) = match taker.self_trade_prevention {
+                    SelfTradePrevention::CancelTaker => (remaining, 0),
+                    SelfTradePrevention::CancelMaker => (0, resting.size),
+                    SelfTradePrevention::DecrementAndCancel => {
+                        let overlap = remaining.min(resting.size);
+                        (overlap, overlap)
+                    }
+                };
+                if maker_cancelled > 0 {
+                    on_self_trade(&resting, maker_cancelled)?;
+                    self.reduce(maker_side, &resting.order, maker_cancelled)?;
+                }
+                remaining -= taker_cancelled;
+                cancelled += taker_cancelled;
+                continue;
+            }
+
+            let fill_size = remaining.min(resting.size);
+            on_fill(&resting, fill_size)?;
+            self.reduce(maker_side, &resting.order, fill_size)?;
+            remaining -= fill_size;
+        }
+
+        Ok(MatchResult {
+            unmatched: remaining,
+            cancelled,
+            limit_reached,
+        })
+    }
+
+    /// Takes `size` off a resting order, removing it once nothing is left.
+    pub fn reduce(&mut self, side: Side, order: &Pubkey, size: u64) -> Result<()> {
+        let book_side = self.side_mut(side);
+        let index = book_side.find(order).ok_or(ErrorCode::OrderNotInBook)?;
+        let entry = &mut book_side.entries[index];
+        entry.size = entry
+            .size
+            .checked_sub(size)
+            .ok_or(ErrorCode::FillExceedsRemaining)?;
+        if entry.size == 0 {
+            book_side.remove(order);
+        }
+        Ok(())
+    }
+
+    fn side(&self, side: Side) -> &BookSide {
+        match side {
+            Side::Yes => &self.bids,
+            Side::No => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BookSide {
+        match side {
+            Side::Yes => &mut self.bids,
+            Side::No => &mut self.asks,
+        }
+    }
+}
+
+/// Result of crossing an incoming order against the book.
+pub struct MatchResult {
+    pub unmatched: u64, // left over to rest in the book
+    pub cancelled: u64, // taken off the incoming order by self-trade prevention or a match limit
+    pub limit_reached: bool,
+}
+
+impl MatchResult {
+    /// Why an incoming order left with nothing to fill or rest was
+    /// cancelled entirely.
+    pub fn cancelled_error(&self) -> ErrorCode {
+        if self.limit_reached {
+            ErrorCode::MatchLimitReached
+        } else {
+            ErrorCode::SelfTradeCancelled
+        }
+    }
+}
+
+#[zero_copy]
+pub struct BookSide {
+    pub len: u64,
+    pub entries: [BookEntry; ORDER_BOOK_DEPTH],
+}
+
+impl BookSide {
+    pub fn entries(&self) -> &[BookEntry] {
+        &self.entries[..self.len as usize]
+    }
+
+    pub fn find(&self, order: &Pubkey) -> Option<usize> {
+        self.entries()
+            .iter()
+            .position(|entry| entry.order == *order)
+    }
+
+    /// Inserts `entry` after every resting order for which `ahead` holds,
+    /// i.e. behind all orders at a better or equal price.
+    fn insert(&mut self, entry: BookEntry, ahead: impl Fn(&BookEntry) -> bool) -> Result<()> {
+        let len = self.len as usize;
+        require!(len < ORDER_BOOK_DEPTH, ErrorCode::OrderBookFull);
+
+        let index = self.entries().partition_point(ahead);
+        self.entries.copy_within(index..len, index + 1);
+        self.entries[index] = entry;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, order: &Pubkey) -> Option<BookEntry> {
+        let len = self.len as usize;
+        let index = self.find(order)?;
+        let entry = self.entries[index];
+
+        self.entries.copy_within(index + 1..len, index);
+        self.entries[len - 1] = BookEntry::default();
+        self.len -= 1;
+        Some(entry)
+    }
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct BookEntry {
+    pub order: Pubkey,
+    pub owner: Pubkey,
+    pub price: u64,
+    pub size: u64,       // unfilled size
+    pub expires_at: i64, // zero if the order never expires
+}
+
+impl BookEntry {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at != 0 && now >= self.expires_at
+    }
+}
+
+/// Fills matched on-chain by `place_order`, waiting for `consume_events`
+/// to settle them. Stored as a ring buffer.
+#[account(zero_copy)]
+pub struct EventQueue {
+    pub market: Pubkey,
+    pub head: u64,
+    pub count: u64,
+    pub events: [FillEvent; EVENT_QUEUE_LEN],
+}
+
+impl EventQueue {
+    pub const LEN: usize = 8 + std::mem::size_of::<EventQueue>();
+
+    pub fn push(&mut self, event: FillEvent) -> Result<()> {
+        require!(
+            (self.count as usize) < EVENT_QUEUE_LEN,
+            ErrorCode::EventQueueFull
+        );
+        let index = (self.head + self.count) as usize % EVENT_QUEUE_LEN;
+        self.events[index] = event;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn peek(&self) -> Option<FillEvent> {
+        (self.count > 0).then(|| self.events[self.head as usize])
+    }
+
+    pub fn pop(&mut self) -> Option<FillEvent> {
+        let event = self.peek()?;
+        self.head = (self.head + 1) % EVENT_QUEUE_LEN as u64;
+        self.count -= 1;
+        Some(event)
+    }
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct FillEvent {
+    pub bid_order: Pubkey,
+    pub ask_order: Pubkey,
+    pub price: u64,
+    pub size: u64,
+    pub taker_side: u8,
+    pub padding: [u8; 7],
+}
+
+impl FillEvent {
+    pub fn new(
+        bid_order: Pubkey,
+        ask_order: Pubkey,
+        price: u64,
+        size: u64,
+        taker_side: Side,
+    ) -> Self {
+        Self {
+            bid_order,
+            ask_order,
+            price,
+            size,
+            taker_side: taker_side as u8,
+            padding: [0; 7],
+        }
+    }
+
+    /// Fill between an incoming `taker_order` on `taker_side` and a resting
+    /// `maker`, at the maker's price.
+    pub fn matched(taker_side: Side, taker_order: Pubkey, maker: &BookEntry, size: u64) -> Self {
+        let (bid_order, ask_order) = match taker_side {
+            Side::Yes => (taker_order, maker.order),
+            Side::No => (maker.order, taker_order),
+        };
+        Self::new(bid_order, ask_order, maker.price, size, taker_side)
+    }
+
+    pub fn taker_side(&self) -> Side {
+        if self.taker_side == Side::Yes as u8 {
+            Side::Yes
+        } else {
+            Side::No
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum MarketKind {
+    Binary,
+    Categorical {
+        outcomes: u8,
+    },
+    /// LONG pays out linearly from nothing at `lower` to one unit of
+    /// collateral at `upper`. Oracle-bound scalar markets quote the bounds
+    /// at the oracle's `threshold_expo`.
+    Scalar {
+        lower: i64,
+        upper: i64,
+    },
+}
+
+impl MarketKind {
+    pub const LEN: usize = 1 + 8 + 8;
+
+    /// Whether the market has YES and NO tokens that trade on an order book.
+    pub fn is_two_sided(&self) -> bool {
+        !matches!(self, MarketKind::Categorical { .. })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Yes,
+    No,
+}
+
+impl Side {
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::Yes => Side::No,
+            Side::No => Side::Yes,
+        }
+    }
+
+    /// Whether an order on this side at `price` matches a resting order on
+    /// the other side at `resting_price`. Both are in YES terms.
+    pub fn crosses(self, price: u64, resting_price: u64) -> bool {
+        match self {
+            Side::Yes => resting_price <= price,
+            Side::No => resting_price >= price,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OracleSource {
+    Pyth,
+    Switchboard,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Comparator {
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Buy,
+    Sell,
+}
+
+/// How a fill moves outcome tokens between the bid and the ask.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FillKind {
+    Mint,     // YES buy against NO buy: a new complete set
+    Transfer, // buy against sell of the same outcome
+    Burn,     // NO sell against YES sell: a complete set given back
+}
+
+impl FillKind {
+    /// A YES buyer and a NO buyer together pay for new complete sets, a NO
+    /// seller and a YES seller together give sets back; anything else just
+    /// changes hands.
+    pub fn between(bid: Direction, ask: Direction) -> Self {
+        match (bid, ask) {
+            (Direction::Buy, Direction::Buy) => FillKind::Mint,
+            (Direction::Sell, Direction::Sell) => FillKind::Burn,
+            _ => FillKind::Transfer,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OrderType {
+    Market, // immediate-or-cancel, with the price as the worst accepted
+    Limit,
+    ImmediateOrCancel,
+    FillOrKill,
+    PostOnly,
+}
+
+/// What happens when an order meets a resting order of the same user. The
+/// incoming order's mode applies.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    CancelTaker,        // cancel the rest of the incoming order
+    CancelMaker,        // cancel the resting order and keep matching
+    DecrementAndCancel, // cancel the overlapping size from both orders
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    Pending,
+    Partial,
+    Filled,
+    Cancelled,
+}
+
+// Events
+#[event]
+pub struct ConfigUpdated {
+    pub admin: Pubkey,
+    pub collateral_mint: Pubkey,
+    pub maker_fee_bps: u16,
+    pub taker_fee_bps: u16,
+    pub min_order_size: u64,
+    pub arbiter: Pubkey,
+    pub resolution_bond: u64,
+    pub dispute_window: i64,
+    pub paused: bool,
+}
+
+#[event]
+pub struct OrderPlaced {
+    pub order_id: Pubkey,
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub client_order_id: u64,
+    pub side: Side,
+    pub direction: Direction,
+    pub order_type: OrderType,
+    pub price: u64, // in basis points, YES terms
+    pub size: u64,
+    pub expires_at: Option<i64>,
+}
+
+#[event]
+pub struct OrderCancelled {
+    pub order_id: Pubkey,
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub client_order_id: u64,
+    pub remaining: u64,
+    pub refund: u64,
+}
+
+#[event]
+pub struct OrderAmended {
+    pub order_id: Pubkey,
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub client_order_id: u64,
+    pub old_price: u64, // in basis points, YES terms
+    pub new_price: u64,
+    pub old_size: u64,
+    pub new_size: u64,
+    pub priority_kept: bool,
+}
+
+#[event]
+pub struct FillSettled {
+    pub bid_order: Pubkey,
+    pub ask_order: Pubkey,
+    pub fill_size: u64,
+    pub fill_price: u64, // in basis points, YES terms
+    pub kind: FillKind,
+}
+
+#[event]
+pub struct FeesCollected {
+    pub market: Pubkey,
+    pub bid_order: Pubkey,
+    pub ask_order: Pubkey,
+    pub maker_fee: u64,
+    pub taker_fee: u64,
+}
+
+#[event]
+pub struct FeesWithdrawn {
+    pub treasury: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ResolutionProposed {
+    pub market: Pubkey,
+    pub proposer: Pubkey,
+    pub outcome: Resolution,
+    pub bond: u64,
+    pub dispute_deadline: i64,
 }
 
-impl Market {
-    pub const LEN: usize = 8 + 32 + 256 + 512 + 8 + 1 + 1 + 2 + 33 + 33 + 8 + 8 + 1;
+#[event]
+pub struct ResolutionDisputed {
+    pub market: Pubkey,
+    pub disputer: Pubkey,
 }
 
-#[account]
-pub struct Order {
+#[event]
+pub struct MarketResolved {
     pub market: Pubkey,
-    pub user: Pubkey,
-    pub side: Side,
-    pub order_type: OrderType,
-    pub price: u64, // in basis points
-    pub size: u64,
-    pub filled: u64,
-    pub status: OrderStatus,
-    pub bump: u8,
+    pub outcome: Resolution,
 }
 
-impl Order {
-    pub const LEN: usize = 8 + 32 + 32 + 1 + 1 + 8 + 8 + 8 + 1 + 1;
+#[event]
+pub struct Deposited {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
-pub enum Side {
-    Yes,
-    No,
+#[event]
+pub struct Withdrawn {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
-pub enum OrderType {
-    Market,
-    Limit,
+#[event]
+pub struct TokensDeposited {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub outcome: Side,
+    pub amount: u64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
-pub enum OrderStatus {
-    Pending,
-    Partial,
-    Filled,
-    Cancelled,
+#[event]
+pub struct TokensWithdrawn {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub outcome: Side,
+    pub amount: u64,
 }
 
-// Events
 #[event]
-pub struct OrderPlaced {
-    pub order_id: Pubkey,
+pub struct Redeemed {
     pub market: Pubkey,
     pub user: Pubkey,
-    pub side: Side,
-    pub order_type: OrderType,
-    pub price: u64,
-    pub size: u64,
+    pub amount: u64,
+    pub payout: u64,
 }
 
 #[event]
-pub struct FillSettled {
-    pub buy_order: Pubkey,
-    pub sell_order: Pubkey,
-    pub fill_size: u64,
-    pub fill_price: u64,
+pub struct CompleteSetSplit {
+    pub market: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
 }
 
 #[event]
-pub struct MarketResolved {
+pub struct CompleteSetMerged {
     pub market: Pubkey,
-    pub outcome: bool,
+    pub user: Pubkey,
+    pub amount: u64,
 }
 
 #[error_code]
@@ -272,4 +3642,1100 @@ pub enum ErrorCode {
     InvalidPrice,
     #[msg("Insufficient balance")]
     InsufficientBalance,
+    #[msg("Order size must be a multiple of the lot size and at least the minimum order size")]
+    InvalidOrderSize,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Outcome mint does not belong to this market")]
+    InvalidOutcomeMint,
+    #[msg("Market has not been resolved")]
+    MarketNotResolved,
+    #[msg("Market is already resolved")]
+    MarketAlreadyResolved,
+    #[msg("Outcome tokens redeem for nothing")]
+    LosingOutcome,
+    #[msg("Amount must be greater than zero")]
+    InvalidAmount,
+    #[msg("Order is not open")]
+    OrderNotOpen,
+    #[msg("Order does not belong to this market")]
+    OrderMarketMismatch,
+    #[msg("Fill size exceeds the remaining order size")]
+    FillExceedsRemaining,
+    #[msg("Title must be between 1 and 32 bytes")]
+    InvalidTitle,
+    #[msg("Description exceeds 512 bytes")]
+    DescriptionTooLong,
+    #[msg("Expiry must be in the future")]
+    InvalidExpiry,
+    #[msg("Market has expired")]
+    MarketExpired,
+    #[msg("Protocol is paused")]
+    ProtocolPaused,
+    #[msg("Fee exceeds the maximum allowed")]
+    InvalidFee,
+    #[msg("Collateral mint is not allowed")]
+    InvalidCollateralMint,
+    #[msg("Order book does not belong to this market")]
+    InvalidOrderBook,
+    #[msg("Order book is full")]
+    OrderBookFull,
+    #[msg("Market already has an order book")]
+    OrderBookAlreadyInitialized,
+    #[msg("Order is not resting in the order book")]
+    OrderNotInBook,
+    #[msg("Event queue does not belong to this market")]
+    InvalidEventQueue,
+    #[msg("Event queue is full")]
+    EventQueueFull,
+    #[msg("Accounts do not match the queued fill")]
+    EventAccountMismatch,
+    #[msg("Token account has the wrong mint or owner")]
+    InvalidTokenAccount,
+    #[msg("Post-only order would take liquidity")]
+    PostOnlyWouldCross,
+    #[msg("Fill-or-kill order could not be filled in full")]
+    FillOrKillNotFilled,
+    #[msg("Market or immediate-or-cancel order matched nothing")]
+    ImmediateOrCancelNotFilled,
+    #[msg("Order has expired")]
+    OrderExpired,
+    #[msg("Order has not expired")]
+    OrderNotExpired,
+    #[msg("Order has matched fills waiting to be settled")]
+    FillsPending,
+    #[msg("Trader account has no free order slots")]
+    TooManyOpenOrders,
+    #[msg("Trader account does not belong to this market and user")]
+    InvalidTraderAccount,
+    #[msg("Resting order cancelled by self-trade prevention was not passed in")]
+    SelfTradeOrderMissing,
+    #[msg("Order would be cancelled entirely by self-trade prevention")]
+    SelfTradeCancelled,
+    #[msg("Market resolves from its oracle")]
+    OracleBound,
+    #[msg("Market has no oracle")]
+    NoOracle,
+    #[msg("Oracle feed does not match the market")]
+    InvalidOracleFeed,
+    #[msg("Oracle feed data is invalid")]
+    InvalidOracleData,
+    #[msg("Oracle threshold exponent, delay or confidence bound is out of range")]
+    InvalidOracleConfig,
+    #[msg("Oracle price was published before expiry")]
+    StaleOraclePrice,
+    #[msg("Dispute window must be positive")]
+    InvalidDisputeWindow,
+    #[msg("A resolution has already been proposed")]
+    ResolutionAlreadyProposed,
+    #[msg("No resolution has been proposed")]
+    NoProposal,
+    #[msg("Proposal has already been disputed")]
+    AlreadyDisputed,
+    #[msg("Proposal has not been disputed")]
+    NotDisputed,
+    #[msg("Dispute window has closed")]
+    DisputeWindowClosed,
+    #[msg("Dispute window is still open")]
+    DisputeWindowOpen,
+    #[msg("A resolution proposal is pending")]
+    ResolutionPending,
+    #[msg("Resolution does not apply to this market")]
+    InvalidResolution,
+    #[msg("Categorical markets need between 2 and MAX_OUTCOMES outcomes")]
+    InvalidOutcomeCount,
+    #[msg("Outcome index is out of range")]
+    InvalidOutcomeIndex,
+    #[msg("Market does not have YES and NO tokens")]
+    NotBinaryMarket,
+    #[msg("Market is not a categorical market")]
+    NotCategoricalMarket,
+    #[msg("Scalar lower bound must be below the upper bound")]
+    InvalidScalarBounds,
+    #[msg("Order still has size open or collateral locked")]
+    OrderStillOpen,
+    #[msg("Order crosses more resting orders than can be matched in one instruction")]
+    MatchLimitReached,
+    #[msg("Oracle price was published too long after expiry")]
+    LateOraclePrice,
+    #[msg("Oracle price confidence interval is too wide")]
+    OracleConfidenceTooWide,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+
+    fn order(
+        user: Pubkey,
+        side: Side,
+        direction: Direction,
+        price: u64,
+        size: u64,
+        fee_bps: u16,
+    ) -> Order {
+        let mut order = Order {
+            market: Pubkey::default(),
+            user,
+            client_order_id: 0,
+            side,
+            direction,
+            order_type: OrderType::Limit,
+            self_trade_prevention: SelfTradePrevention::CancelTaker,
+            price,
+            size,
+            filled: 0,
+            status: OrderStatus::Pending,
+            fee_bps,
+            locked: 0,
+            expires_at: None,
+            bump: 0,
+        };
+        order.locked = order.reserved_for(size).unwrap();
+        order
+    }
+
+    /// Stores `order` in an account owned by the program, as instructions
+    /// load it.
+    fn order_account(order: &Order) -> Account<'static, Order> {
+        let mut data = Vec::new();
+        order.try_serialize(&mut data).unwrap();
+        let info = AccountInfo::new(
+            Box::leak(Box::new(Pubkey::new_unique())),
+            false,
+            true,
+            Box::leak(Box::new(1)),
+            Box::leak(data.into_boxed_slice()),
+            &crate::ID,
+            false,
+            0,
+        );
+        Account::try_from(Box::leak(Box::new(info))).unwrap()
+    }
+
+    fn trader(free_collateral: u64) -> TraderAccount {
+        TraderAccount {
+            market: Pubkey::default(),
+            owner: Pubkey::default(),
+            open_orders: Vec::new(),
+            free_collateral,
+            locked_collateral: 0,
+            yes_position: 0,
+            no_position: 0,
+            locked_yes: 0,
+            locked_no: 0,
+            yes_cost_basis: 0,
+            no_cost_basis: 0,
+            realized_pnl: 0,
+            bump: 0,
+        }
+    }
+
+    /// Places `order` for `trader`, locking what it needs, then settles a
+    /// fill of `size` at `price` and `fee_bps` against it.
+    fn place_and_fill(
+        trader: &mut TraderAccount,
+        order: &Order,
+        size: u64,
+        price: u64,
+        fee_bps: u16,
+    ) -> Account<'static, Order> {
+        let mut order = order_account(order);
+        trader.open_order(order.key(), order.locked).unwrap();
+        if order.direction == Direction::Sell {
+            trader.lock_tokens(order.side, order.size).unwrap();
+        }
+        let amounts = order.apply_fill(size, price, fee_bps).unwrap();
+        trader.record_fill(&order, size, &amounts).unwrap();
+        order
+    }
+
+    fn entry(owner: Pubkey, price: u64, size: u64, expires_at: i64) -> BookEntry {
+        BookEntry {
+            order: Pubkey::new_unique(),
+            owner,
+            price,
+            size,
+            expires_at,
+        }
+    }
+
+    fn book_with_asks(asks: &[BookEntry]) -> Box<OrderBook> {
+        let mut book = Box::new(OrderBook::zeroed());
+        for ask in asks {
+            book.insert(Side::No, *ask).unwrap();
+        }
+        book
+    }
+
+    /// Sizes reported per resting order.
+    type Reported = Vec<(Pubkey, u64)>;
+
+    /// Matches `taker` against `book` at `now`, returning the result and
+    /// the fills and self-trade cancellations reported.
+    fn cross(
+        book: &mut OrderBook,
+        taker: &Order,
+        size: u64,
+        now: i64,
+    ) -> (MatchResult, Reported, Reported) {
+        let mut fills = Vec::new();
+        let mut self_trades = Vec::new();
+        let result = book
+            .match_order(
+                taker,
+                size,
+                now,
+                |resting, size| {
+                    fills.push((resting.order, size));
+                    Ok(())
+                },
+                |resting, size| {
+                    self_trades.push((resting.order, size));
+                    Ok(())
+                },
+            )
+            .unwrap();
+        (result, fills, self_trades)
+    }
+
+    fn orders(side: &BookSide) -> Vec<Pubkey> {
+        side.entries().iter().map(|entry| entry.order).collect()
+    }
+
+    #[test]
+    fn insert_keeps_price_time_priority() {
+        let owner = Pubkey::new_unique();
+        let (bid_a, bid_b, bid_c) = (
+            entry(owner, 5_000, 1, 0),
+            entry(owner, 6_000, 1, 0),
+            entry(owner, 5_000, 1, 0),
+        );
+        let (ask_a, ask_b, ask_c) = (
+            entry(owner, 7_000, 1, 0),
+            entry(owner, 6_500, 1, 0),
+            entry(owner, 7_000, 1, 0),
+        );
+        let mut book = Box::new(OrderBook::zeroed());
+        for bid in [bid_a, bid_b, bid_c] {
+            book.insert(Side::Yes, bid).unwrap();
+        }
+        for ask in [ask_a, ask_b, ask_c] {
+            book.insert(Side::No, ask).unwrap();
+        }
+
+        assert_eq!(orders(&book.bids), [bid_b.order, bid_a.order, bid_c.order]);
+        assert_eq!(orders(&book.asks), [ask_b.order, ask_a.order, ask_c.order]);
+    }
+
+    #[test]
+    fn insert_rejects_a_full_side() {
+        let owner = Pubkey::new_unique();
+        let mut book = Box::new(OrderBook::zeroed());
+        for _ in 0..ORDER_BOOK_DEPTH {
+            book.insert(Side::Yes, entry(owner, 5_000, 1, 0)).unwrap();
+        }
+        assert!(book.insert(Side::Yes, entry(owner, 5_000, 1, 0)).is_err());
+        assert!(book.insert(Side::No, entry(owner, 5_000, 1, 0)).is_ok());
+    }
+
+    #[test]
+    fn remove_and_reduce_take_orders_off_the_book() {
+        let owner = Pubkey::new_unique();
+        let (a, b, c) = (
+            entry(owner, 6_000, 100, 0),
+            entry(owner, 6_500, 100, 0),
+            entry(owner, 7_000, 100, 0),
+        );
+        let mut book = book_with_asks(&[a, b, c]);
+
+        assert_eq!(book.remove(Side::No, &b.order).unwrap().order, b.order);
+        assert!(book.remove(Side::No, &b.order).is_none());
+        assert_eq!(orders(&book.asks), [a.order, c.order]);
+        assert_eq!(book.asks.entries[2].order, Pubkey::default());
+
+        book.reduce(Side::No, &a.order, 40).unwrap();
+        assert_eq!(book.get(Side::No, &a.order).unwrap().size, 60);
+        assert!(book.reduce(Side::No, &a.order, 61).is_err());
+        book.reduce(Side::No, &a.order, 60).unwrap();
+        assert_eq!(orders(&book.asks), [c.order]);
+        assert!(book.reduce(Side::No, &a.order, 1).is_err());
+    }
+
+    #[test]
+    fn match_order_fills_the_best_prices_first() {
+        let (maker, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (a, b, c) = (
+            entry(maker, 7_000, 100, 0),
+            entry(maker, 6_500, 100, 0),
+            entry(maker, 7_500, 100, 0),
+        );
+        let mut book = book_with_asks(&[a, b, c]);
+        let buy = order(taker, Side::Yes, Direction::Buy, 7_000, 300, 0);
+
+        let (result, fills, self_trades) = cross(&mut book, &buy, 150, 0);
+        assert_eq!(fills, [(b.order, 100), (a.order, 50)]);
+        assert!(self_trades.is_empty());
+        assert_eq!((result.unmatched, result.cancelled), (0, 0));
+        assert_eq!(book.get(Side::No, &a.order).unwrap().size, 50);
+
+        // Only the first two levels cross the limit price
+        let (result, fills, _) = cross(&mut book, &buy, 150, 0);
+        assert_eq!(fills, [(a.order, 50)]);
+        assert_eq!((result.unmatched, result.cancelled), (100, 0));
+        assert_eq!(orders(&book.asks), [c.order]);
+    }
+
+    #[test]
+    fn match_order_skips_expired_orders() {
+        let (maker, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (expired, live) = (entry(maker, 6_000, 100, 10), entry(maker, 6_500, 100, 20));
+        let mut book = book_with_asks(&[expired, live]);
+        let buy = order(taker, Side::Yes, Direction::Buy, 7_000, 100, 0);
+
+        let (result, fills, _) = cross(&mut book, &buy, 50, 10);
+        assert_eq!(fills, [(live.order, 50)]);
+        assert_eq!(result.unmatched, 0);
+        // Expired orders stay on the book until cancelled
+        assert_eq!(book.get(Side::No, &expired.order).unwrap().size, 100);
+
+        let (_, fills, _) = cross(&mut book, &buy, 50, 9);
+        assert_eq!(fills, [(expired.order, 50)]);
+    }
+
+    #[test]
+    fn match_order_from_a_sell_crosses_the_same_book_side() {
+        let (maker, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ask = entry(maker, 6_000, 100, 0);
+        let mut book = book_with_asks(&[ask]);
+        // Selling NO at 4_000 is bidding 6_000 for YES
+        let sell = order(taker, Side::No, Direction::Sell, 6_000, 100, 0);
+
+        let (result, fills, _) = cross(&mut book, &sell, 100, 0);
+        assert_eq!(fills, [(ask.order, 100)]);
+        assert_eq!(result.unmatched, 0);
+    }
+
+    #[test]
+    fn self_trade_prevention_cancel_taker() {
+        let (user, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (own, theirs) = (entry(user, 6_000, 100, 0), entry(other, 6_500, 100, 0));
+        let mut book = book_with_asks(&[own, theirs]);
+        let buy = order(user, Side::Yes, Direction::Buy, 7_000, 150, 0);
+
+        let (result, fills, self_trades) = cross(&mut book, &buy, 150, 0);
+        assert!(fills.is_empty() && self_trades.is_empty());
+        assert_eq!((result.unmatched, result.cancelled), (0, 150));
+        assert!(matches!(
+            result.cancelled_error(),
+            ErrorCode::SelfTradeCancelled
+        ));
+        assert_eq!(orders(&book.asks), [own.order, theirs.order]);
+    }
+
+    #[test]
+    fn self_trade_prevention_cancel_maker() {
+        let (user, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (own, theirs) = (entry(user, 6_000, 100, 0), entry(other, 6_500, 100, 0));
+        let mut book = book_with_asks(&[own, theirs]);
+        let mut buy = order(user, Side::Yes, Direction::Buy, 7_000, 150, 0);
+        buy.self_trade_prevention = SelfTradePrevention::CancelMaker;
+
+        let (result, fills, self_trades) = cross(&mut book, &buy, 150, 0);
+        assert_eq!(self_trades, [(own.order, 100)]);
+        assert_eq!(fills, [(theirs.order, 100)]);
+        assert_eq!((result.unmatched, result.cancelled), (50, 0));
+        assert!(book.asks.entries().is_empty());
+    }
+
+    #[test]
+    fn self_trade_prevention_decrement_and_cancel() {
+        let (user, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (own, theirs) = (entry(user, 6_000, 100, 0), entry(other, 6_500, 100, 0));
+        let mut book = book_with_asks(&[own, theirs]);
+        let mut buy = order(user, Side::Yes, Direction::Buy, 7_000, 150, 0);
+        buy.self_trade_prevention = SelfTradePrevention::DecrementAndCancel;
+
+        let (result, fills, self_trades) = cross(&mut book, &buy, 150, 0);
+        assert_eq!(self_trades, [(own.order, 100)]);
+        assert_eq!(fills, [(theirs.order, 50)]);
+        assert_eq!((result.unmatched, result.cancelled), (0, 100));
+        assert_eq!(orders(&book.asks), [theirs.order]);
+
+        let mut book = book_with_asks(&[entry(user, 6_000, 100, 0)]);
+        let (result, _, self_trades) = cross(&mut book, &buy, 40, 0);
+        assert_eq!(self_trades[0].1, 40);
+        assert_eq!(result.cancelled, 40);
+        assert_eq!(book.asks.entries()[0].size, 60);
+    }
+
+    #[test]
+    fn match_order_cancels_what_crosses_past_the_match_limit() {
+        let (maker, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let asks: Vec<_> = (0..MAX_MATCHES_PER_ORDER + 8)
+            .map(|_| entry(maker, 5_000, 1, 0))
+            .collect();
+        let mut book = book_with_asks(&asks);
+        let buy = order(taker, Side::Yes, Direction::Buy, 5_000, 100, 0);
+
+        let (result, fills, _) = cross(&mut book, &buy, 100, 0);
+        assert_eq!(fills.len(), MAX_MATCHES_PER_ORDER);
+        assert_eq!((result.unmatched, result.cancelled), (0, 68));
+        assert!(result.limit_reached);
+        assert!(matches!(
+            result.cancelled_error(),
+            ErrorCode::MatchLimitReached
+        ));
+        assert_eq!(book.asks.entries().len(), 8);
+
+        // Nothing is cancelled once the rest no longer crosses
+        let mut book = book_with_asks(&asks[..MAX_MATCHES_PER_ORDER]);
+        book.insert(Side::No, entry(maker, 6_000, 1, 0)).unwrap();
+        let (result, fills, _) = cross(&mut book, &buy, 100, 0);
+        assert_eq!(fills.len(), MAX_MATCHES_PER_ORDER);
+        assert_eq!((result.unmatched, result.cancelled), (68, 0));
+        assert!(!result.limit_reached);
+    }
+
+    #[test]
+    fn match_order_skips_expired_orders_past_the_match_limit() {
+        let (maker, taker) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let buy = order(taker, Side::Yes, Direction::Buy, 5_000, 100, 0);
+
+        // Expired orders do not count toward the match limit
+        let mut asks: Vec<_> = (0..MAX_MATCHES_PER_ORDER * 2)
+            .map(|_| entry(maker, 5_000, 1, 10))
+            .collect();
+        let live = entry(maker, 5_000, 100, 0);
+        asks.push(live);
+        let mut book = book_with_asks(&asks);
+        let (result, fills, _) = cross(&mut book, &buy, 100, 10);
+        assert_eq!(fills, [(live.order, 100)]);
+        assert_eq!((result.unmatched, result.cancelled), (0, 0));
+
+        // but are bounded separately
+        let mut asks: Vec<_> = (0..MAX_EXPIRED_SKIPS_PER_ORDER + 1)
+            .map(|_| entry(maker, 5_000, 1, 10))
+            .collect();
+        asks.push(live);
+        let mut book = book_with_asks(&asks);
+        let (result, fills, _) = cross(&mut book, &buy, 100, 10);
+        assert!(fills.is_empty());
+        assert_eq!((result.unmatched, result.cancelled), (0, 100));
+        assert!(result.limit_reached);
+    }
+
+    #[test]
+    fn would_cross_ignores_expired_orders() {
+        let maker = Pubkey::new_unique();
+        let mut book = book_with_asks(&[entry(maker, 5_000, 100, 10), entry(maker, 6_000, 100, 0)]);
+
+        assert!(book.would_cross(Side::Yes, 5_500, 9));
+        assert!(!book.would_cross(Side::Yes, 5_500, 10));
+        assert!(book.would_cross(Side::Yes, 6_000, 10));
+        assert!(!book.would_cross(Side::No, 5_000, 0));
+
+        book.insert(Side::Yes, entry(maker, 5_000, 100, 0)).unwrap();
+        assert!(book.would_cross(Side::No, 5_000, 0));
+        assert!(!book.would_cross(Side::No, 5_001, 0));
+    }
+
+    #[test]
+    fn event_queue_wraps_around() {
+        let fill = |size| FillEvent {
+            size,
+            ..Default::default()
+        };
+        let mut queue = Box::new(EventQueue::zeroed());
+        assert!(queue.pop().is_none());
+
+        for size in 0..EVENT_QUEUE_LEN as u64 {
+            queue.push(fill(size)).unwrap();
+        }
+        assert!(queue.push(fill(0)).is_err());
+
+        for size in 0..3 {
+            assert_eq!(queue.pop().unwrap().size, size);
+        }
+        for size in 0..3 {
+            queue.push(fill(EVENT_QUEUE_LEN as u64 + size)).unwrap();
+        }
+        assert!(queue.push(fill(0)).is_err());
+
+        for size in 3..EVENT_QUEUE_LEN as u64 + 3 {
+            assert_eq!(queue.peek().unwrap().size, size);
+            assert_eq!(queue.pop().unwrap().size, size);
+        }
+        assert!(queue.pop().is_none());
+        assert_eq!((queue.head, queue.count), (3, 0));
+    }
+
+    #[test]
+    fn apply_fill_releases_reserved_collateral() {
+        let mut buy = order(
+            Pubkey::default(),
+            Side::Yes,
+            Direction::Buy,
+            6_000,
+            10_000,
+            100,
+        );
+        assert_eq!(buy.locked, 6_060);
+
+        // A better price and a lower fee rate are credited back
+        let fill = buy.apply_fill(4_000, 5_000, 50).unwrap();
+        assert_eq!((fill.released, fill.fee, fill.credited), (2_424, 10, 414));
+        assert!(buy.status == OrderStatus::Partial);
+        assert_eq!(buy.locked, 3_636);
+
+        // The fee rate is capped at the rate reserved for
+        let fill = buy.apply_fill(6_000, 6_000, 200).unwrap();
+        assert_eq!((fill.released, fill.fee, fill.credited), (3_636, 36, 0));
+        assert!(buy.status == OrderStatus::Filled);
+        assert_eq!(buy.locked, 0);
+
+        let mut sell = order(
+            Pubkey::default(),
+            Side::Yes,
+            Direction::Sell,
+            6_000,
+            1_000,
+            100,
+        );
+        assert_eq!(sell.locked, 0);
+        let fill = sell.apply_fill(1_000, 6_500, 100).unwrap();
+        assert_eq!((fill.released, fill.fee, fill.credited), (0, 6, 644));
+        assert!(sell.status == OrderStatus::Filled);
+    }
+
+    #[test]
+    fn apply_fill_releases_rounding_dust_on_the_last_fill() {
+        let mut buy = order(
+            Pubkey::default(),
+            Side::Yes,
+            Direction::Buy,
+            3_333,
+            30_001,
+            30,
+        );
+        assert_eq!(buy.locked, 10_028);
+
+        let mut released = 0;
+        for (size, credited) in [(10_000, 0), (10_000, 0), (10_001, 2)] {
+            let fill = buy.apply_fill(size, 3_333, 30).unwrap();
+            assert_eq!(fill.credited, credited);
+            released += fill.released;
+        }
+        assert_eq!(released, 10_028);
+        assert_eq!(buy.locked, 0);
+
+        let mut no = order(Pubkey::default(), Side::No, Direction::Buy, 3_333, 3, 0);
+        assert_eq!(no.locked, 2);
+        assert_eq!(no.apply_fill(1, 3_333, 0).unwrap().released, 0);
+        let fill = no.apply_fill(2, 3_333, 0).unwrap();
+        assert_eq!((fill.released, fill.credited), (2, 1));
+    }
+
+    #[test]
+    fn cancel_refunds_unfilled_collateral() {
+        let mut buy = order(
+            Pubkey::default(),
+            Side::Yes,
+            Direction::Buy,
+            6_000,
+            10_000,
+            100,
+        );
+
+        // Fills still pending settlement keep their collateral locked
+        assert_eq!(buy.cancel(6_000).unwrap(), 3_636);
+        assert_eq!((buy.size, buy.locked), (4_000, 2_424));
+        assert!(buy.status == OrderStatus::Pending);
+
+        let fill = buy.apply_fill(4_000, 6_000, 100).unwrap();
+        assert_eq!((fill.released, fill.fee, fill.credited), (2_424, 24, 0));
+        assert!(buy.status == OrderStatus::Filled);
+
+        // Once every fill is settled, the cancel refunds the rest
+        let mut buy = order(
+            Pubkey::default(),
+            Side::Yes,
+            Direction::Buy,
+            6_000,
+            10_000,
+            100,
+        );
+        buy.apply_fill(2_500, 6_000, 100).unwrap();
+        assert_eq!(buy.cancel(7_500).unwrap(), 4_545);
+        assert_eq!((buy.size, buy.locked), (2_500, 0));
+        assert!(buy.status == OrderStatus::Cancelled);
+
+        assert!(buy.cancel(2_501).is_err());
+    }
+
+    #[test]
+    fn payout_binary_and_invalid() {
+        let kind = MarketKind::Binary;
+        let payouts =
+            |resolution: Resolution| (resolution.payout(kind, 0), resolution.payout(kind, 1));
+        assert_eq!(payouts(Resolution::Yes), (PRICE_SCALE, 0));
+        assert_eq!(payouts(Resolution::No), (0, PRICE_SCALE));
+        assert_eq!(
+            payouts(Resolution::Invalid { yes_payout: 3_000 }),
+            (3_000, 7_000)
+        );
+    }
+
+    #[test]
+    fn payout_categorical() {
+        let kind = MarketKind::Categorical { outcomes: 4 };
+        let winner = Resolution::Outcome { index: 2 };
+        let payouts: Vec<_> = (0..4).map(|index| winner.payout(kind, index)).collect();
+        assert_eq!(payouts, [0, 0, PRICE_SCALE, 0]);
+
+        let invalid = Resolution::Invalid { yes_payout: 2_500 };
+        assert!((0..4).all(|index| invalid.payout(kind, index) == 2_500));
+    }
+
+    #[test]
+    fn payout_scalar_is_linear_and_clamped() {
+        let kind = MarketKind::Scalar {
+            lower: 100,
+            upper: 300,
+        };
+        let payouts = |value| {
+            let resolution = Resolution::Scalar { value };
+            (resolution.payout(kind, 0), resolution.payout(kind, 1))
+        };
+        assert_eq!(payouts(150), (2_500, 7_500));
+        assert_eq!(payouts(300), (PRICE_SCALE, 0));
+        assert_eq!(payouts(50), (0, PRICE_SCALE));
+        assert_eq!(payouts(i64::MAX), (PRICE_SCALE, 0));
+
+        let kind = MarketKind::Scalar {
+            lower: i64::MIN,
+            upper: i64::MAX,
+        };
+        assert_eq!(Resolution::Scalar { value: 0 }.payout(kind, 0), 5_000);
+    }
+
+    fn oracle(threshold: i64, threshold_expo: i32, comparator: Comparator) -> OracleConfig {
+        OracleConfig {
+            source: OracleSource::Pyth,
+            feed: Pubkey::new_unique(),
+            threshold,
+            threshold_expo,
+            comparator,
+            max_delay: 3_600,
+            max_confidence_bps: 100,
+        }
+    }
+
+    fn price(price: i64, expo: i32) -> OraclePrice {
+        OraclePrice {
+            price,
+            conf: 0,
+            expo,
+            publish_time: 0,
+        }
+    }
+
+    #[test]
+    fn evaluate_compares_across_exponents() {
+        let at_threshold = price(5_000_000, -2);
+        let above = price(5_000_001, -2);
+        let below = price(499, 2);
+
+        let greater = oracle(50_000, 0, Comparator::GreaterThan);
+        assert!(!greater.evaluate(&at_threshold).unwrap());
+        assert!(greater.evaluate(&above).unwrap());
+
+        let greater_or_equal = oracle(50_000, 0, Comparator::GreaterThanOrEqual);
+        assert!(greater_or_equal.evaluate(&at_threshold).unwrap());
+        assert!(!greater_or_equal.evaluate(&below).unwrap());
+
+        let less = oracle(500, 2, Comparator::LessThan);
+        assert!(less.evaluate(&below).unwrap());
+        assert!(!less.evaluate(&at_threshold).unwrap());
+
+        let less_or_equal = oracle(5, 4, Comparator::LessThanOrEqual);
+        assert!(less_or_equal.evaluate(&at_threshold).unwrap());
+        assert!(!less_or_equal.evaluate(&above).unwrap());
+    }
+
+    #[test]
+    fn value_rescales_toward_zero() {
+        let config = oracle(0, -2, Comparator::GreaterThan);
+        assert_eq!(config.value(&price(123_456, -4)).unwrap(), 1_234);
+        assert_eq!(config.value(&price(-123_456, -4)).unwrap(), -1_234);
+        assert_eq!(config.value(&price(12, 0)).unwrap(), 1_200);
+        assert_eq!(config.value(&price(7, -2)).unwrap(), 7);
+
+        let config = oracle(0, -OracleConfig::MAX_EXPO, Comparator::GreaterThan);
+        assert!(config.value(&price(i64::MAX, 0)).is_err());
+    }
+
+    /// Resolves a market of `kind` expiring at time 100 from a feed owned
+    /// by `owner` holding `data`.
+    fn resolve_from(
+        config: &OracleConfig,
+        kind: MarketKind,
+        owner: &Pubkey,
+        data: &mut [u8],
+    ) -> Result<Resolution> {
+        let key = config.feed;
+        let mut lamports = 0;
+        let feed = AccountInfo::new(&key, false, false, &mut lamports, data, owner, false, 0);
+        config.resolve(kind, &feed, 100)
+    }
+
+    #[test]
+    fn resolve_reads_a_mock_pyth_feed() {
+        let config = oracle(50_000, 0, Comparator::GreaterThan);
+        let mut data = [0; pyth::LEN];
+
+        mock_oracle::write_pyth_price(&mut data, 5_000_001, 0, -2, 100).unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert!(outcome.unwrap() == Resolution::Yes);
+
+        mock_oracle::write_pyth_price(&mut data, 5_000_000, 0, -2, 150).unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert!(outcome.unwrap() == Resolution::No);
+
+        // Prices published before expiry are stale
+        mock_oracle::write_pyth_price(&mut data, 5_000_001, 0, -2, 99).unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert_eq!(outcome.err().unwrap(), ErrorCode::StaleOraclePrice.into());
+
+        mock_oracle::write_pyth_price(&mut data, 5_000_001, 0, OracleConfig::MAX_EXPO + 1, 100)
+            .unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert_eq!(outcome.err().unwrap(), ErrorCode::InvalidOracleData.into());
+    }
+
+    #[test]
+    fn resolve_bounds_publish_delay_and_confidence() {
+        let config = oracle(50_000, 0, Comparator::GreaterThan);
+        let mut data = [0; pyth::LEN];
+
+        mock_oracle::write_pyth_price(&mut data, 5_000_001, 0, -2, 3_700).unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert!(outcome.unwrap() == Resolution::Yes);
+
+        mock_oracle::write_pyth_price(&mut data, 5_000_001, 0, -2, 3_701).unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert_eq!(outcome.err().unwrap(), ErrorCode::LateOraclePrice.into());
+
+        // Up to 1% of the price either way
+        mock_oracle::write_pyth_price(&mut data, 5_000_001, 50_000, -2, 100).unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert!(outcome.unwrap() == Resolution::Yes);
+
+        mock_oracle::write_pyth_price(&mut data, 5_000_001, 50_001, -2, 100).unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert_eq!(
+            outcome.err().unwrap(),
+            ErrorCode::OracleConfidenceTooWide.into()
+        );
+
+        let mut data = [0; switchboard::LEN];
+        let config = OracleConfig {
+            source: OracleSource::Switchboard,
+            ..config
+        };
+        mock_oracle::write_switchboard_result(&mut data, 5_000_001, 50_001, 2, 100).unwrap();
+        let outcome = resolve_from(&config, MarketKind::Binary, &mock_oracle::ID, &mut data);
+        assert_eq!(
+            outcome.err().unwrap(),
+            ErrorCode::OracleConfidenceTooWide.into()
+        );
+    }
+
+    #[test]
+    fn switchboard_deviation_is_quoted_at_the_result_scale() {
+        let mut data = [0; switchboard::LEN];
+        mock_oracle::write_switchboard_result(&mut data, 123_456, 0, 4, 100).unwrap();
+        let deviation = switchboard::STD_DEVIATION_MANTISSA_OFFSET;
+        let scale = switchboard::STD_DEVIATION_SCALE_OFFSET;
+
+        // 0.12345 at scale 5 rounds up to 0.1235 at scale 4
+        data[deviation..deviation + 16].copy_from_slice(&12_345i128.to_le_bytes());
+        data[scale..scale + 4].copy_from_slice(&5u32.to_le_bytes());
+        assert_eq!(read_switchboard_price(&data).unwrap().conf, 1_235);
+
+        // 1.2 at scale 1 is 1.2000 at scale 4
+        data[deviation..deviation + 16].copy_from_slice(&12i128.to_le_bytes());
+        data[scale..scale + 4].copy_from_slice(&1u32.to_le_bytes());
+        assert_eq!(read_switchboard_price(&data).unwrap().conf, 12_000);
+
+        data[deviation..deviation + 16].copy_from_slice(&(-1i128).to_le_bytes());
+        assert!(read_switchboard_price(&data).is_err());
+    }
+
+    #[test]
+    fn resolve_reads_a_mock_switchboard_feed() {
+        let config = OracleConfig {
+            source: OracleSource::Switchboard,
+            ..oracle(0, -2, Comparator::GreaterThan)
+        };
+        let kind = MarketKind::Scalar {
+            lower: 0,
+            upper: 10_000,
+        };
+        let mut data = [0; switchboard::LEN];
+
+        mock_oracle::write_switchboard_result(&mut data, 123_456, 1_234, 4, 100).unwrap();
+        let outcome = resolve_from(&config, kind, &mock_oracle::ID, &mut data);
+        assert!(outcome.unwrap() == Resolution::Scalar { value: 1_234 });
+
+        // A feed in the wrong layout for the source is rejected
+        let pyth_config = OracleConfig {
+            source: OracleSource::Pyth,
+            ..config
+        };
+        let outcome = resolve_from(&pyth_config, kind, &mock_oracle::ID, &mut data);
+        assert_eq!(outcome.err().unwrap(), ErrorCode::InvalidOracleData.into());
+    }
+
+    #[test]
+    fn resolve_rejects_feeds_of_other_programs() {
+        let config = oracle(50_000, 0, Comparator::GreaterThan);
+        let mut data = [0; pyth::LEN];
+        mock_oracle::write_pyth_price(&mut data, 5_000_001, 0, -2, 100).unwrap();
+
+        let outcome = resolve_from(
+            &config,
+            MarketKind::Binary,
+            &Pubkey::new_unique(),
+            &mut data,
+        );
+        assert_eq!(outcome.err().unwrap(), ErrorCode::InvalidOracleFeed.into());
+
+        // Pyth's own feeds are trusted for Pyth configs only
+        let outcome = resolve_from(&config, MarketKind::Binary, &pyth::PROGRAM_ID, &mut data);
+        assert!(outcome.unwrap() == Resolution::Yes);
+        let switchboard_config = OracleConfig {
+            source: OracleSource::Switchboard,
+            ..config
+        };
+        let outcome = resolve_from(
+            &switchboard_config,
+            MarketKind::Binary,
+            &pyth::PROGRAM_ID,
+            &mut data,
+        );
+        assert_eq!(outcome.err().unwrap(), ErrorCode::InvalidOracleFeed.into());
+    }
+
+    #[test]
+    fn record_fill_realizes_pnl_against_average_cost() {
+        let user = Pubkey::default();
+        let mut trader = trader(6_060);
+
+        // Buying adds the cost and the fee to the cost basis
+        let buy = order(user, Side::Yes, Direction::Buy, 6_000, 10_000, 100);
+        place_and_fill(&mut trader, &buy, 10_000, 6_000, 100);
+        assert_eq!(
+            (trader.yes_position, trader.yes_cost_basis),
+            (10_000, 6_060)
+        );
+        assert_eq!((trader.free_collateral, trader.locked_collateral), (0, 0));
+        assert_eq!(trader.realized_pnl, 0);
+        assert!(trader.open_orders.is_empty());
+
+        // Selling 40% realizes the net proceeds less 40% of the cost
+        let sell = order(user, Side::Yes, Direction::Sell, 7_000, 4_000, 100);
+        place_and_fill(&mut trader, &sell, 4_000, 7_000, 100);
+        assert_eq!(trader.free_collateral, 2_772);
+        assert_eq!((trader.yes_position, trader.locked_yes), (6_000, 0));
+        assert_eq!((trader.yes_cost_basis, trader.realized_pnl), (3_636, 348));
+
+        // Withdrawn tokens take their cost with them
+        trader.withdraw_tokens(Side::Yes, 3_000).unwrap();
+        assert_eq!((trader.yes_cost_basis, trader.realized_pnl), (1_818, 348));
+        assert!(trader.withdraw_tokens(Side::Yes, 3_001).is_err());
+
+        // Selling the rest at a loss releases all of the remaining cost
+        let sell = order(user, Side::Yes, Direction::Sell, 5_000, 3_000, 0);
+        place_and_fill(&mut trader, &sell, 3_000, 5_000, 0);
+        assert_eq!((trader.yes_position, trader.yes_cost_basis), (0, 0));
+        assert_eq!(trader.realized_pnl, 30);
+        assert_eq!(trader.no_cost_basis, 0);
+    }
+
+    #[test]
+    fn deposited_tokens_carry_no_cost_basis() {
+        let user = Pubkey::default();
+        let mut trader = trader(4_000);
+
+        let buy = order(user, Side::No, Direction::Buy, 6_000, 10_000, 0);
+        place_and_fill(&mut trader, &buy, 10_000, 6_000, 0);
+        trader.deposit_tokens(Side::No, 10_000).unwrap();
+        assert_eq!((trader.no_position, trader.no_cost_basis), (20_000, 4_000));
+
+        // Half the position sold carries half the average cost
+        let sell = order(user, Side::No, Direction::Sell, 5_000, 10_000, 0);
+        place_and_fill(&mut trader, &sell, 10_000, 5_000, 0);
+        assert_eq!((trader.no_cost_basis, trader.realized_pnl), (2_000, 3_000));
+    }
+
+    #[test]
+    fn lock_tokens_moves_outcome_tokens_into_sell_orders() {
+        let mut trader = trader(0);
+        trader.deposit_tokens(Side::Yes, 100).unwrap();
+
+        trader.lock_tokens(Side::Yes, 60).unwrap();
+        assert_eq!((trader.yes_position, trader.locked_yes), (40, 60));
+        assert!(trader.lock_tokens(Side::Yes, 41).is_err());
+        assert!(trader.lock_tokens(Side::No, 1).is_err());
+
+        assert!(trader.unlock_tokens(Side::Yes, 61).is_err());
+        trader.unlock_tokens(Side::Yes, 60).unwrap();
+        assert_eq!((trader.yes_position, trader.locked_yes), (100, 0));
+        assert_eq!((trader.no_position, trader.locked_no), (0, 0));
+    }
+
+    #[test]
+    fn record_cancel_returns_collateral_and_tokens() {
+        let user = Pubkey::default();
+        let mut trader = trader(6_060);
+
+        let mut buy = order_account(&order(user, Side::Yes, Direction::Buy, 6_000, 10_000, 100));
+        trader.open_order(buy.key(), buy.locked).unwrap();
+        let refund = buy.cancel(4_000).unwrap();
+        trader.record_cancel(&buy, 4_000, refund).unwrap();
+        assert_eq!(
+            (trader.free_collateral, trader.locked_collateral),
+            (2_424, 3_636)
+        );
+        // The rest of the order is still open
+        assert_eq!(trader.open_orders, [buy.key()]);
+
+        let refund = buy.cancel(6_000).unwrap();
+        trader.record_cancel(&buy, 6_000, refund).unwrap();
+        assert_eq!(
+            (trader.free_collateral, trader.locked_collateral),
+            (6_060, 0)
+        );
+        assert!(trader.open_orders.is_empty());
+
+        trader.deposit_tokens(Side::No, 5_000).unwrap();
+        let mut sell = order_account(&order(user, Side::No, Direction::Sell, 3_000, 5_000, 100));
+        trader.open_order(sell.key(), sell.locked).unwrap();
+        trader.lock_tokens(Side::No, 5_000).unwrap();
+        let refund = sell.cancel(5_000).unwrap();
+        trader.record_cancel(&sell, 5_000, refund).unwrap();
+        assert_eq!(refund, 0);
+        assert_eq!((trader.no_position, trader.locked_no), (5_000, 0));
+        assert_eq!(trader.free_collateral, 6_060);
+        assert!(trader.open_orders.is_empty());
+    }
+
+    #[test]
+    fn fills_between_buys_mint_and_between_sells_burn() {
+        assert!(FillKind::between(Direction::Buy, Direction::Buy) == FillKind::Mint);
+        assert!(FillKind::between(Direction::Sell, Direction::Sell) == FillKind::Burn);
+        assert!(FillKind::between(Direction::Buy, Direction::Sell) == FillKind::Transfer);
+        assert!(FillKind::between(Direction::Sell, Direction::Buy) == FillKind::Transfer);
+
+        let mut market = Market {
+            creator: Pubkey::default(),
+            title: String::new(),
+            description: String::new(),
+            expiry_timestamp: 0,
+            kind: MarketKind::Binary,
+            is_active: true,
+            is_resolved: false,
+            resolution: None,
+            oracle: None,
+            proposal: None,
+            yes_token_mint: None,
+            no_token_mint: None,
+            yes_token_supply: 0,
+            no_token_supply: 0,
+            collateral_mint: Pubkey::default(),
+            order_book: Pubkey::default(),
+            event_queue: Pubkey::default(),
+            bump: 0,
+            vault_bump: 0,
+            outcome_mint_bumps: [0; MAX_OUTCOMES as usize],
+        };
+        let supply = |market: &Market| (market.yes_token_supply, market.no_token_supply);
+
+        market.record_fill_supply(FillKind::Mint, 300).unwrap();
+        assert_eq!(supply(&market), (300, 300));
+        market.record_fill_supply(FillKind::Transfer, 100).unwrap();
+        assert_eq!(supply(&market), (300, 300));
+        market.record_fill_supply(FillKind::Burn, 100).unwrap();
+        assert_eq!(supply(&market), (200, 200));
+        assert!(market.record_fill_supply(FillKind::Burn, 201).is_err());
+        assert_eq!(supply(&market), (200, 200));
+    }
+
+    #[test]
+    fn validate_binary_resolutions() {
+        let kind = MarketKind::Binary;
+        assert!(Resolution::Yes.validate(kind).is_ok());
+        assert!(Resolution::No.validate(kind).is_ok());
+        assert!(Resolution::Invalid { yes_payout: 0 }.validate(kind).is_ok());
+        assert!(Resolution::Invalid {
+            yes_payout: PRICE_SCALE
+        }
+        .validate(kind)
+        .is_ok());
+        assert!(Resolution::Invalid {
+            yes_payout: PRICE_SCALE + 1
+        }
+        .validate(kind)
+        .is_err());
+        assert!(Resolution::Outcome { index: 0 }.validate(kind).is_err());
+        assert!(Resolution::Scalar { value: 0 }.validate(kind).is_err());
+    }
+
+    #[test]
+    fn validate_categorical_resolutions() {
+        let kind = MarketKind::Categorical { outcomes: 3 };
+        assert!(Resolution::Outcome { index: 2 }.validate(kind).is_ok());
+        assert!(Resolution::Outcome { index: 3 }.validate(kind).is_err());
+
+        // Every outcome must be refunded equally, which rounds down
+        assert!(Resolution::Invalid { yes_payout: 3_333 }
+            .validate(kind)
+            .is_ok());
+        assert!(Resolution::Invalid { yes_payout: 3_334 }
+            .validate(kind)
+            .is_err());
+        assert!(Resolution::Invalid { yes_payout: 5_000 }
+            .validate(kind)
+            .is_err());
+
+        assert!(Resolution::Yes.validate(kind).is_err());
+        assert!(Resolution::Scalar { value: 0 }.validate(kind).is_err());
+    }
+
+    #[test]
+    fn validate_scalar_resolutions() {
+        let kind = MarketKind::Scalar {
+            lower: -100,
+            upper: 100,
+        };
+        // Values outside the range are clamped when paid out
+        for value in [i64::MIN, -100, 0, 100, i64::MAX] {
+            assert!(Resolution::Scalar { value }.validate(kind).is_ok());
+        }
+        assert!(Resolution::Invalid {
+            yes_payout: PRICE_SCALE / 2
+        }
+        .validate(kind)
+        .is_ok());
+        assert!(Resolution::Invalid {
+            yes_payout: PRICE_SCALE + 1
+        }
+        .validate(kind)
+        .is_err());
+
+        assert!(Resolution::Yes.validate(kind).is_err());
+        assert!(Resolution::Outcome { index: 0 }.validate(kind).is_err());
+    }
 }
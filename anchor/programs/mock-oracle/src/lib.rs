@@ -0,0 +1,115 @@
+use anchor_lang::prelude::*;
+
+declare_id!("GZsvz4beFEzLraueUpSRZ4sFe59irrbw6ecKbo2hVVKL");
+
+/// Stand-in for the oracle feeds markets resolve from. Tests create a
+/// zeroed feed account owned by this program and write prices into it in
+/// the layout the exchange's Pyth or Switchboard adapter reads.
+#[program]
+pub mod mock_oracle {
+    use super::*;
+
+    pub fn set_pyth_price(
+        ctx: Context<SetPrice>,
+        price: i64,
+        conf: u64,
+        expo: i32,
+        publish_time: i64,
+    ) -> Result<()> {
+        let mut data = ctx.accounts.feed.try_borrow_mut_data()?;
+        write_pyth_price(&mut data, price, conf, expo, publish_time)
+    }
+
+    pub fn set_switchboard_result(
+        ctx: Context<SetPrice>,
+        mantissa: i128,
+        std_deviation: i128,
+        scale: u32,
+        round_open_timestamp: i64,
+    ) -> Result<()> {
+        let mut data = ctx.accounts.feed.try_borrow_mut_data()?;
+        write_switchboard_result(
+            &mut data,
+            mantissa,
+            std_deviation,
+            scale,
+            round_open_timestamp,
+        )
+    }
+}
+
+/// Writes a trading Pyth v2 price account worth `price * 10^expo`, give or
+/// take `conf`, into `data`.
+pub fn write_pyth_price(
+    data: &mut [u8],
+    price: i64,
+    conf: u64,
+    expo: i32,
+    publish_time: i64,
+) -> Result<()> {
+    require!(data.len() >= pyth::LEN, ErrorCode::FeedTooSmall);
+
+    write(data, 0, &pyth::MAGIC.to_le_bytes());
+    write(data, 4, &pyth::VERSION.to_le_bytes());
+    write(data, 8, &pyth::PRICE_ACCOUNT.to_le_bytes());
+    write(data, 20, &expo.to_le_bytes());
+    write(data, 96, &publish_time.to_le_bytes()); // timestamp
+    write(data, 208, &price.to_le_bytes()); // aggregate price
+    write(data, 216, &conf.to_le_bytes()); // aggregate confidence
+    write(data, 224, &pyth::STATUS_TRADING.to_le_bytes()); // aggregate status
+
+    Ok(())
+}
+
+/// Writes a Switchboard v2 aggregator whose latest confirmed round is
+/// `mantissa / 10^scale`, with a standard deviation of
+/// `std_deviation / 10^scale`, into `data`.
+pub fn write_switchboard_result(
+    data: &mut [u8],
+    mantissa: i128,
+    std_deviation: i128,
+    scale: u32,
+    round_open_timestamp: i64,
+) -> Result<()> {
+    require!(data.len() >= switchboard::LEN, ErrorCode::FeedTooSmall);
+
+    write(data, 0, &switchboard::DISCRIMINATOR);
+    write(data, 341, &1u32.to_le_bytes()); // num_success
+    write(data, 358, &round_open_timestamp.to_le_bytes());
+    write(data, 366, &mantissa.to_le_bytes()); // result
+    write(data, 382, &scale.to_le_bytes());
+    write(data, 386, &std_deviation.to_le_bytes());
+    write(data, 402, &scale.to_le_bytes());
+
+    Ok(())
+}
+
+fn write(data: &mut [u8], offset: usize, bytes: &[u8]) {
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+mod pyth {
+    pub const MAGIC: u32 = 0xa1b2_c3d4;
+    pub const VERSION: u32 = 2;
+    pub const PRICE_ACCOUNT: u32 = 3;
+    pub const STATUS_TRADING: u32 = 1;
+    pub const LEN: usize = 240;
+}
+
+mod switchboard {
+    pub const DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+    pub const LEN: usize = 406;
+}
+
+#[derive(Accounts)]
+pub struct SetPrice<'info> {
+    /// CHECK: raw feed account in a foreign layout, written byte by byte
+    #[account(mut, owner = crate::ID)]
+    pub feed: UncheckedAccount<'info>,
+}
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Feed account is too small for the layout")]
+    FeedTooSmall,
+}